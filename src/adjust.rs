@@ -0,0 +1,173 @@
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Black and white input points for each of the red, green and blue channels.
+/// Values at or below the black point become 0 and values at or above the
+/// white point become 255.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Levels(pub [(u8, u8); 3]);
+
+impl FromStr for Levels {
+    type Err = String;
+
+    /// Parses `black:white` for all channels, or `r_black:r_white,g_black:g_white,b_black:b_white`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_pair = |pair: &str| -> Result<(u8, u8), String> {
+            let (black, white) = pair
+                .split_once(':')
+                .ok_or_else(|| format!("expected black:white, got '{}'", pair))?;
+            let black: u8 = black.trim().parse().map_err(|e| format!("{}", e))?;
+            let white: u8 = white.trim().parse().map_err(|e| format!("{}", e))?;
+            if black >= white {
+                return Err(format!(
+                    "black point {} must be below white point {}",
+                    black, white
+                ));
+            }
+            Ok((black, white))
+        };
+
+        let pairs = s
+            .split(',')
+            .map(parse_pair)
+            .collect::<Result<Vec<_>, _>>()?;
+        match pairs[..] {
+            [all] => Ok(Levels([all; 3])),
+            [r, g, b] => Ok(Levels([r, g, b])),
+            _ => Err("expected one black:white pair or one per channel".to_string()),
+        }
+    }
+}
+
+/// Tone and color corrections applied to each frame before it is converted.
+/// The default leaves frames untouched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Adjustments {
+    pub levels: Option<Levels>,
+    /// Spread the brightness of every frame evenly over the full range.
+    pub equalize: bool,
+    pub gamma: f32,
+    pub contrast: f32,
+    pub brightness: f32,
+    pub saturation: f32,
+    pub invert: bool,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Adjustments {
+            levels: None,
+            equalize: false,
+            gamma: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            saturation: 1.0,
+            invert: false,
+        }
+    }
+}
+
+impl Adjustments {
+    pub fn is_identity(&self) -> bool {
+        *self == Adjustments::default()
+    }
+
+    /// Precomputes the per-value tables, so applying the adjustments to a
+    /// frame is a few lookups per pixel.
+    pub fn prepare(&self) -> PreparedAdjustments {
+        let levels: [[u8; 256]; 3] = std::array::from_fn(|c| {
+            let (black, white) = self.levels.map_or((0, 255), |l| l.0[c]);
+            std::array::from_fn(|v| {
+                let scaled = (v as f32 - black as f32) / (white as f32 - black as f32);
+                (scaled.clamp(0.0, 1.0) * 255.0).round() as u8
+            })
+        });
+        let tone: [u8; 256] = std::array::from_fn(|v| {
+            let mut x = (v as f32 / 255.0).powf(1.0 / self.gamma);
+            x = (x - 0.5) * self.contrast + 0.5 + self.brightness;
+            let x = (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+            if self.invert { 255 - x } else { x }
+        });
+
+        PreparedAdjustments {
+            identity: self.is_identity(),
+            levels,
+            equalize: self.equalize,
+            tone,
+            saturation: self.saturation,
+        }
+    }
+}
+
+/// `Adjustments` ready to be applied, shared by the threads converting frames.
+pub struct PreparedAdjustments {
+    identity: bool,
+    levels: [[u8; 256]; 3],
+    equalize: bool,
+    tone: [u8; 256],
+    saturation: f32,
+}
+
+impl PreparedAdjustments {
+    pub fn apply(&self, mut img: RgbImage) -> RgbImage {
+        if self.identity {
+            return img;
+        }
+
+        for px in img.pixels_mut() {
+            for c in 0..3 {
+                px.0[c] = self.levels[c][px.0[c] as usize];
+            }
+        }
+        if self.equalize {
+            equalize(&mut img);
+        }
+        for px in img.pixels_mut() {
+            let mut rgb = px.0.map(|v| self.tone[v as usize] as f32);
+            if self.saturation != 1.0 {
+                let luma = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+                rgb = rgb.map(|v| luma + (v - luma) * self.saturation);
+            }
+            px.0 = rgb.map(|v| v.round().clamp(0.0, 255.0) as u8);
+        }
+        img
+    }
+}
+
+/// Histogram equalization of the brightness. Each pixel is scaled as a whole
+/// so hues are kept.
+fn equalize(img: &mut RgbImage) {
+    let luma = |p: [u8; 3]| {
+        (0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32).round() as usize
+    };
+    let mut histogram = [0u32; 256];
+    for px in img.pixels() {
+        histogram[luma(px.0).min(255)] += 1;
+    }
+    let total = img.width() * img.height();
+    let Some(first) = histogram.iter().position(|&n| n > 0) else {
+        return;
+    };
+    let first_count = histogram[first];
+    if total == first_count {
+        return;
+    }
+
+    let mut mapping = [0f32; 256];
+    let mut cumulative = 0u32;
+    for (v, &n) in histogram.iter().enumerate() {
+        cumulative += n;
+        mapping[v] =
+            cumulative.saturating_sub(first_count) as f32 / (total - first_count) as f32 * 255.0;
+    }
+    for px in img.pixels_mut() {
+        let y = luma(px.0).min(255);
+        if y == 0 {
+            continue;
+        }
+        let scale = mapping[y] / y as f32;
+        px.0 =
+            px.0.map(|v| (v as f32 * scale).round().clamp(0.0, 255.0) as u8);
+    }
+}
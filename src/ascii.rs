@@ -1,57 +1,442 @@
-use crate::{
-    config::{ASCII_CHARS, CHAR_ASPECT_RATIO},
-    error::AppError,
+use crate::adjust::Adjustments;
+use crate::charset;
+use crate::config::{
+    BRAILLE_MIN_CONTRAST, CHAR_ASPECT_RATIO, DELTA_MERGE_GAP, EDGE_CELL_SIZE, EDGE_MIN_MAGNITUDE,
+    EDGE_MIN_PIXELS, GLYPH_MATCH_MIN_CONTRAST, MAX_FRAME_CELLS,
 };
+use crate::dither::{Dither, ErrorDiffuser, bayer};
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::quantize::Quantization;
 use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb, RgbImage, imageops::FilterType};
-use indicatif::{ProgressBar, ProgressStyle};
-use log::{debug, error, info};
+use log::debug;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::{
-    path::{Path, PathBuf},
-    sync::Mutex,
-};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+const BRAILLE_BASE: u32 = 0x2800;
+
+/// Pixel offsets within a 2x4 block in the bit order of the Braille dots
+/// (U+2800 plus the bit mask gives the pattern).
+const BRAILLE_DOTS: [(u32, u32); 8] = [
+    (0, 0),
+    (0, 1),
+    (0, 2),
+    (1, 0),
+    (1, 1),
+    (1, 2),
+    (0, 3),
+    (1, 3),
+];
+
+/// Characters for edges running vertically, horizontally, rising, falling,
+/// and horizontally below a bright area. They follow the ramp in the glyph
+/// table of the edge mode.
+const EDGE_GLYPHS: [char; 5] = ['|', '-', '/', '\\', '_'];
+
+/// How pixels are mapped onto terminal cells.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum RenderMode {
+    /// One pixel per cell, drawn as a character from the ASCII ramp.
+    #[default]
+    Ascii,
+    /// Two vertically stacked pixels per cell, drawn as an upper half block
+    /// with the top pixel as foreground and the bottom one as background.
+    HalfBlock,
+    /// A 2x4 block of pixels per cell, drawn as a Braille pattern with one
+    /// dot per pixel that is brighter than its surroundings.
+    Braille,
+    /// A block of pixels per cell, drawn as the ramp character whose shape in
+    /// the bundled bitmap font matches the block best.
+    GlyphMatch,
+    /// A block of pixels per cell, drawn as a line character following the
+    /// direction of strong edges and as a ramp character elsewhere.
+    Edges,
+    /// One pixel per cell, painted as the cell's background with a blank on
+    /// top. Needs colors, so it cannot be used with `--color none`.
+    Background,
+    /// Like `Background`, with the ramp character for the pixel's brightness
+    /// drawn on top in black or white, whichever stands out more.
+    BackgroundAscii,
+}
+
+impl RenderMode {
+    /// Number of source pixels covered by one cell, horizontally and vertically.
+    pub fn subpixels(self) -> (u32, u32) {
+        match self {
+            RenderMode::Ascii | RenderMode::Background | RenderMode::BackgroundAscii => (1, 1),
+            RenderMode::HalfBlock => (1, 2),
+            RenderMode::Braille => (2, 4),
+            RenderMode::GlyphMatch => (GLYPH_WIDTH, GLYPH_HEIGHT),
+            RenderMode::Edges => (EDGE_CELL_SIZE, EDGE_CELL_SIZE),
+        }
+    }
+
+    /// Whether cells are drawn with characters from the ramp.
+    pub fn uses_ramp(self) -> bool {
+        matches!(
+            self,
+            RenderMode::Ascii
+                | RenderMode::GlyphMatch
+                | RenderMode::Edges
+                | RenderMode::BackgroundAscii
+        )
+    }
+
+    /// Whether the picture is carried by background colors, which are lost
+    /// without color output.
+    pub fn uses_background(self) -> bool {
+        matches!(
+            self,
+            RenderMode::HalfBlock | RenderMode::Background | RenderMode::BackgroundAscii
+        )
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RenderMode::Ascii => "ascii",
+            RenderMode::HalfBlock => "half-block",
+            RenderMode::Braille => "braille",
+            RenderMode::GlyphMatch => "glyph-match",
+            RenderMode::Edges => "edges",
+            RenderMode::Background => "background",
+            RenderMode::BackgroundAscii => "background-ascii",
+        }
+    }
+}
+
+/// Everything that decides how a frame is converted. It is stored in the
+/// cache header so a cache is drawn the way it was rendered.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RenderSettings {
+    pub mode: RenderMode,
+    /// Characters for the ASCII mode, from dark to bright.
+    pub ramp: Vec<char>,
+    pub dither: Dither,
+    pub adjustments: Adjustments,
+    /// Drop colors while converting, so runs only break where the glyph changes.
+    pub monochrome: bool,
+    pub quantization: Quantization,
+}
+
+impl RenderSettings {
+    /// Characters that the `ascii_idx` of a cell refers to.
+    pub fn glyphs(&self) -> Vec<char> {
+        match self.mode {
+            RenderMode::Ascii | RenderMode::GlyphMatch | RenderMode::BackgroundAscii => {
+                self.ramp.clone()
+            }
+            RenderMode::Background => vec![' '],
+            RenderMode::Edges => self.ramp.iter().chain(&EDGE_GLYPHS).copied().collect(),
+            RenderMode::HalfBlock => vec!['\u{2580}'],
+            RenderMode::Braille => (0..=255u32)
+                .map(|i| char::from_u32(BRAILLE_BASE + i).unwrap())
+                .collect(),
+        }
+    }
+
+    /// Short tag that keeps caches rendered with different settings apart.
+    /// Empty for the defaults, so existing caches keep their names.
+    pub fn cache_key(&self) -> String {
+        let mut parts = Vec::new();
+        if self.mode != RenderMode::Ascii {
+            parts.push(self.mode.name().to_string());
+        }
+        if self.mode.uses_ramp() {
+            match charset::builtin_name(&self.ramp) {
+                Some("dense") => {}
+                Some(name) => parts.push(name.to_string()),
+                None => {
+                    let ramp: String = self.ramp.iter().collect();
+                    parts.push(format!("custom{}", short_hash(ramp.as_bytes())));
+                }
+            }
+        }
+        if self.mode == RenderMode::Ascii && self.dither != Dither::None {
+            parts.push(self.dither.name().to_string());
+        }
+        if self.monochrome {
+            parts.push("mono".to_string());
+        } else if !self.quantization.is_none() {
+            parts.push(format!(
+                "{}{}",
+                self.quantization.method.name(),
+                self.quantization.tolerance
+            ));
+        }
+        if !self.adjustments.is_identity() {
+            let adjustments = format!("{:?}", self.adjustments);
+            parts.push(format!("adj{}", short_hash(adjustments.as_bytes())));
+        }
+        parts.join("-")
+    }
+}
+
+/// First four bytes of the SHA-256 of `bytes`, in hex.
+fn short_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)[..4]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RleRun {
     pub ascii_idx: u8,
     pub color: [u8; 3],
+    pub bg: Option<[u8; 3]>,
     pub count: u16,
 }
 
+impl RleRun {
+    fn cell(&self) -> Cell {
+        Cell {
+            ascii_idx: self.ascii_idx,
+            color: self.color,
+            bg: self.bg,
+        }
+    }
+}
+
+/// A frame as runs of identical cells. Runs never cross the end of a row and
+/// hold at most `u16::MAX` cells; longer stretches are split.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RleFrame {
-    pub width: u16,
+    pub width: u32,
     pub runs: Vec<RleRun>,
+    /// When the frame is shown, counted from the first frame. The cache keeps
+    /// it in its index rather than with the runs.
+    #[serde(skip)]
+    pub pts: Duration,
 }
 
-pub fn resize_and_center(img: &DynamicImage, cols: u16, lines: u16) -> RgbImage {
-    debug!("Resizing image to fit terminal: {}x{}", cols, lines);
+/// A single character position of a decoded frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ascii_idx: u8,
+    pub color: [u8; 3],
+    pub bg: Option<[u8; 3]>,
+}
 
-    let term_w = cols as u32;
-    let term_h = lines.saturating_sub(1) as u32;
-    if term_w == 0 || term_h == 0 {
-        return ImageBuffer::from_pixel(1, 1, Rgb([0, 0, 0]));
+impl RleFrame {
+    /// Expands the runs into one cell per character position, row by row.
+    pub fn cells(&self) -> Vec<Cell> {
+        let total: usize = self.runs.iter().map(|r| r.count as usize).sum();
+        let mut cells = Vec::with_capacity(total);
+        for run in &self.runs {
+            cells.extend(std::iter::repeat_n(run.cell(), run.count as usize));
+        }
+        cells
     }
-    let (w, h) = img.dimensions();
-    if w == 0 || h == 0 {
-        return ImageBuffer::from_pixel(term_w, term_h, Rgb([0, 0, 0]));
+
+    /// The same glyphs with every color cleared, re-encoded so that runs only
+    /// break where the glyph changes.
+    pub fn without_colors(&self) -> Self {
+        let cells: Vec<Cell> = self
+            .cells()
+            .into_iter()
+            .map(|c| Cell {
+                ascii_idx: c.ascii_idx,
+                color: [0; 3],
+                bg: None,
+            })
+            .collect();
+        RleFrame {
+            pts: self.pts,
+            ..RleFrame::from_cells(self.width, &cells)
+        }
     }
+
+    /// Checks the layout of a frame read from outside the program, so that
+    /// expanding it cannot exhaust memory or misplace cells.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 {
+            return if self.runs.is_empty() {
+                Ok(())
+            } else {
+                Err("frame without width has runs".to_string())
+            };
+        }
+        let width = self.width as u64;
+        let mut total = 0u64;
+        for run in &self.runs {
+            if run.count == 0 {
+                return Err("run of zero cells".to_string());
+            }
+            if total % width + run.count as u64 > width {
+                return Err(format!("run crosses the end of row {}", total / width));
+            }
+            total += run.count as u64;
+            if total > MAX_FRAME_CELLS {
+                return Err(format!("frame has more than {} cells", MAX_FRAME_CELLS));
+            }
+        }
+        if !total.is_multiple_of(width) {
+            return Err(format!(
+                "{} cells do not fill rows of {} cells",
+                total, self.width
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn from_cells(width: u32, cells: &[Cell]) -> Self {
+        let runs = if width == 0 {
+            Vec::new()
+        } else {
+            cells.chunks(width as usize).flat_map(encode_runs).collect()
+        };
+        RleFrame {
+            width,
+            runs,
+            pts: Duration::ZERO,
+        }
+    }
+}
+
+/// The cells that changed between two consecutive frames of the same size.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaFrame {
+    pub spans: Vec<DeltaSpan>,
+}
+
+/// A stretch of changed cells, starting `skip` unchanged cells after the end
+/// of the previous span.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaSpan {
+    pub skip: u32,
+    pub runs: Vec<RleRun>,
+}
+
+impl DeltaFrame {
+    pub fn between(previous: &[Cell], next: &[Cell]) -> Self {
+        let n = previous.len().min(next.len());
+        let mut spans = Vec::new();
+        let mut pos = 0;
+        let mut i = 0;
+        while i < n {
+            if previous[i] == next[i] {
+                i += 1;
+                continue;
+            }
+            // Short stretches of unchanged cells are cheaper to repeat than to
+            // start a new span for
+            let mut end = i + 1;
+            let mut j = end;
+            while j < n && j - end <= DELTA_MERGE_GAP {
+                if previous[j] != next[j] {
+                    end = j + 1;
+                }
+                j += 1;
+            }
+            spans.push(DeltaSpan {
+                skip: (i - pos) as u32,
+                runs: encode_runs(&next[i..end]),
+            });
+            pos = end;
+            i = end;
+        }
+        DeltaFrame { spans }
+    }
+
+    pub fn run_count(&self) -> usize {
+        self.spans.iter().map(|s| s.runs.len()).sum()
+    }
+
+    /// Applies the changes in place. Returns `false` if a span reaches past
+    /// the end of `cells`, i.e. the delta does not belong to this frame.
+    pub fn apply(&self, cells: &mut [Cell]) -> bool {
+        let mut pos = 0usize;
+        for span in &self.spans {
+            pos += span.skip as usize;
+            for run in &span.runs {
+                let end = pos + run.count as usize;
+                if end > cells.len() {
+                    return false;
+                }
+                cells[pos..end].fill(run.cell());
+                pos = end;
+            }
+        }
+        true
+    }
+}
+
+fn encode_runs(cells: &[Cell]) -> Vec<RleRun> {
+    let mut runs: Vec<RleRun> = Vec::new();
+    for cell in cells {
+        match runs.last_mut() {
+            Some(r) if r.cell() == *cell && r.count < u16::MAX => r.count += 1,
+            _ => runs.push(RleRun {
+                ascii_idx: cell.ascii_idx,
+                color: cell.color,
+                bg: cell.bg,
+                count: 1,
+            }),
+        }
+    }
+    runs
+}
+
+/// Size of the character grid a frame is rendered into, leaving the last line
+/// free for the status bar.
+pub fn canvas_dimensions(cols: u16, lines: u16) -> (u32, u32) {
+    (cols as u32, lines.saturating_sub(1) as u32)
+}
+
+/// Size of the pixel canvas behind the character grid, which is larger than
+/// the grid when a mode packs several pixels into one cell.
+pub fn pixel_canvas_dimensions(cols: u16, lines: u16, mode: RenderMode) -> (u32, u32) {
+    let (term_w, term_h) = canvas_dimensions(cols, lines);
+    let (sx, sy) = mode.subpixels();
+    (term_w * sx, term_h * sy)
+}
+
+/// Size an image of `w`x`h` pixels should be scaled to so that it fits the
+/// terminal grid while keeping its aspect ratio on screen.
+pub fn fit_dimensions(w: u32, h: u32, cols: u16, lines: u16, mode: RenderMode) -> (u32, u32) {
+    let (term_w, term_h) = pixel_canvas_dimensions(cols, lines, mode);
+    if w == 0 || h == 0 || term_w == 0 || term_h == 0 {
+        return (term_w.max(1), term_h.max(1));
+    }
+    let (sx, sy) = mode.subpixels();
+    let pixel_aspect_ratio = CHAR_ASPECT_RATIO * sx as f32 / sy as f32;
     let mut nw = term_w;
-    let mut nh = ((h as f32 / w as f32 * nw as f32) / CHAR_ASPECT_RATIO).round() as u32;
+    let mut nh = ((h as f32 / w as f32 * nw as f32) / pixel_aspect_ratio).round() as u32;
     if nh > term_h {
         let s = term_h as f32 / nh as f32;
         nh = term_h;
         nw = (nw as f32 * s).round() as u32;
     }
-    nw = nw.max(1);
-    nh = nh.max(1);
-    let filter = if nw < w || nh < h {
-        FilterType::Triangle
+    (nw.max(1), nh.max(1))
+}
+
+pub fn resize_and_center(img: &DynamicImage, cols: u16, lines: u16, mode: RenderMode) -> RgbImage {
+    debug!("Resizing image to fit terminal: {}x{}", cols, lines);
+
+    let (term_w, term_h) = pixel_canvas_dimensions(cols, lines, mode);
+    if term_w == 0 || term_h == 0 {
+        return ImageBuffer::from_pixel(1, 1, Rgb([0, 0, 0]));
+    }
+    let (w, h) = img.dimensions();
+    if w == 0 || h == 0 {
+        return ImageBuffer::from_pixel(term_w, term_h, Rgb([0, 0, 0]));
+    }
+    let (nw, nh) = fit_dimensions(w, h, cols, lines, mode);
+    let r = if (nw, nh) == (w, h) {
+        // Frames piped from ffmpeg are already scaled to the grid
+        img.to_rgb8()
     } else {
-        FilterType::CatmullRom
+        let filter = if nw < w || nh < h {
+            FilterType::Triangle
+        } else {
+            FilterType::CatmullRom
+        };
+        img.resize_exact(nw, nh, filter).to_rgb8()
     };
-    let r = img.resize_exact(nw, nh, filter).to_rgb8();
+    if (nw, nh) == (term_w, term_h) {
+        return r;
+    }
     let mut canvas = ImageBuffer::from_pixel(term_w, term_h, Rgb([0, 0, 0]));
     let sx = (term_w - nw) / 2;
     let sy = (term_h - nh) / 2;
@@ -59,104 +444,566 @@ pub fn resize_and_center(img: &DynamicImage, cols: u16, lines: u16) -> RgbImage
     canvas
 }
 
-pub fn convert_image_to_ascii(img: &RgbImage) -> RleFrame {
+/// Relative luminance of a pixel, from 0.0 to 1.0.
+fn luminance(px: [u8; 3]) -> f32 {
+    (0.2126 * px[0] as f32 + 0.7152 * px[1] as f32 + 0.0722 * px[2] as f32) / 255.0
+}
+
+pub fn convert_image_to_ascii(img: &RgbImage, ramp: &[char], dither: Dither) -> RleFrame {
     let (w, h) = img.dimensions();
     if w == 0 || h == 0 {
-        return RleFrame {
-            width: 0,
-            runs: Vec::new(),
-        };
+        return RleFrame::from_cells(0, &[]);
     }
-    let na = ASCII_CHARS.len() as f32;
-    let mi = (ASCII_CHARS.len() - 1) as u8;
-    let mut runs = Vec::new();
+    let na = ramp.len() as f32;
+    let mi = ramp.len().saturating_sub(1) as u8;
+    let mut diffuser = ErrorDiffuser::<1>::new(dither, w as usize);
+    let mut cells = Vec::with_capacity((w * h) as usize);
     for y in 0..h {
-        let mut cur: Option<RleRun> = None;
         for x in 0..w {
             let px = img.get_pixel(x, y).0;
-            let intensity =
-                (0.2126 * px[0] as f32 + 0.7152 * px[1] as f32 + 0.0722 * px[2] as f32) / 255.0;
+            let intensity = luminance(px);
             let idx = if na > 1.0 {
-                (intensity * (na - 1.0)).round() as u8
+                let steps = na - 1.0;
+                let value = match dither {
+                    Dither::Ordered => intensity + bayer(x as usize, y as usize) / steps,
+                    _ => intensity + diffuser.error_at(x as usize)[0],
+                };
+                let level = (value * steps).round().clamp(0.0, steps);
+                diffuser.diffuse(x as usize, [value - level / steps]);
+                level as u8
             } else {
                 0
             };
-            let idx = idx.min(mi);
-            match cur.as_mut() {
-                Some(r) if r.ascii_idx == idx && r.color == px => r.count = r.count.wrapping_add(1),
-                Some(_) => {
-                    runs.push(cur.take().unwrap());
-                    cur = Some(RleRun {
-                        ascii_idx: idx,
-                        color: px,
-                        count: 1,
-                    });
-                }
-                None => {
-                    cur = Some(RleRun {
-                        ascii_idx: idx,
-                        color: px,
-                        count: 1,
-                    })
+            cells.push(Cell {
+                ascii_idx: idx.min(mi),
+                color: px,
+                bg: None,
+            });
+        }
+        diffuser.next_row();
+    }
+    RleFrame::from_cells(w, &cells)
+}
+
+/// Packs each pair of vertically adjacent pixels into one upper half block
+/// cell. The image must be twice as tall as the character grid.
+pub fn convert_image_to_half_blocks(img: &RgbImage) -> RleFrame {
+    let (w, h) = img.dimensions();
+    let rows = h / 2;
+    let mut cells = Vec::with_capacity((w * rows) as usize);
+    for y in 0..rows {
+        for x in 0..w {
+            cells.push(Cell {
+                ascii_idx: 0,
+                color: img.get_pixel(x, y * 2).0,
+                bg: Some(img.get_pixel(x, y * 2 + 1).0),
+            });
+        }
+    }
+    RleFrame::from_cells(w, &cells)
+}
+
+/// Turns each 2x4 block of pixels into a Braille pattern. A dot is raised
+/// where the pixel is brighter than the block average, or everywhere in a
+/// flat block that is bright overall. The cell takes the average color of
+/// its raised dots.
+pub fn convert_image_to_braille(img: &RgbImage) -> RleFrame {
+    let (w, h) = img.dimensions();
+    let (cols, rows) = (w / 2, h / 4);
+    let mut cells = Vec::with_capacity((cols * rows) as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            let mut block = [[0u8; 3]; 8];
+            let mut luma = [0f32; 8];
+            for (i, &(dx, dy)) in BRAILLE_DOTS.iter().enumerate() {
+                let px = img.get_pixel(col * 2 + dx, row * 4 + dy).0;
+                block[i] = px;
+                luma[i] = luminance(px);
+            }
+            let (min, max) = luma
+                .iter()
+                .fold((1.0f32, 0.0f32), |(lo, hi), &l| (lo.min(l), hi.max(l)));
+            let mean = luma.iter().sum::<f32>() / 8.0;
+            let threshold = if max - min >= BRAILLE_MIN_CONTRAST {
+                mean
+            } else {
+                0.5
+            };
+
+            let mut pattern = 0u8;
+            let mut sum = [0u32; 3];
+            for i in 0..8 {
+                if luma[i] >= threshold {
+                    pattern |= 1 << i;
+                    for c in 0..3 {
+                        sum[c] += block[i][c] as u32;
+                    }
                 }
             }
+            let lit = pattern.count_ones().max(1);
+            cells.push(Cell {
+                ascii_idx: pattern,
+                color: sum.map(|c| (c / lit) as u8),
+                bg: None,
+            });
         }
-        if let Some(r) = cur {
-            runs.push(r);
+    }
+    RleFrame::from_cells(cols, &cells)
+}
+
+/// Picks for each block of `GLYPH_WIDTH`x`GLYPH_HEIGHT` pixels the ramp
+/// character whose font glyph covers the pixels brighter than the block
+/// average most closely (least squares over the binarized block). Flat blocks
+/// have no shape to match and fall back to the brightness ramp.
+pub fn convert_image_to_matched_glyphs(img: &RgbImage, ramp: &[char]) -> RleFrame {
+    let (w, h) = img.dimensions();
+    let (cols, rows) = (w / GLYPH_WIDTH, h / GLYPH_HEIGHT);
+    let block_len = (GLYPH_WIDTH * GLYPH_HEIGHT) as usize;
+    let last = ramp.len().saturating_sub(1) as f32;
+    // Only characters the font can draw take part in the matching
+    let candidates: Vec<(u8, u64)> = ramp
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &c)| font::glyph_mask(c).map(|mask| (i as u8, mask)))
+        .collect();
+
+    let mut cells = Vec::with_capacity((cols * rows) as usize);
+    let mut block = vec![[0u8; 3]; block_len];
+    let mut luma = vec![0f32; block_len];
+    for row in 0..rows {
+        for col in 0..cols {
+            for y in 0..GLYPH_HEIGHT {
+                for x in 0..GLYPH_WIDTH {
+                    let i = (y * GLYPH_WIDTH + x) as usize;
+                    block[i] = img
+                        .get_pixel(col * GLYPH_WIDTH + x, row * GLYPH_HEIGHT + y)
+                        .0;
+                    luma[i] = luminance(block[i]);
+                }
+            }
+            let (min, max) = luma
+                .iter()
+                .fold((1.0f32, 0.0f32), |(lo, hi), &l| (lo.min(l), hi.max(l)));
+            let mean = luma.iter().sum::<f32>() / block_len as f32;
+
+            let mut shape = 0u64;
+            if max - min >= GLYPH_MATCH_MIN_CONTRAST {
+                for (i, &l) in luma.iter().enumerate() {
+                    if l > mean {
+                        shape |= 1 << i;
+                    }
+                }
+            }
+            let matched = if shape == 0 {
+                None
+            } else {
+                candidates
+                    .iter()
+                    .min_by_key(|(_, mask)| (mask ^ shape).count_ones())
+                    .map(|&(idx, _)| idx)
+            };
+
+            // Color the glyph with its lit pixels, or the whole block when
+            // falling back to the ramp
+            let mut sum = [0u32; 3];
+            let mut lit = 0;
+            for (i, px) in block.iter().enumerate() {
+                if matched.is_none() || shape & (1 << i) != 0 {
+                    for c in 0..3 {
+                        sum[c] += px[c] as u32;
+                    }
+                    lit += 1;
+                }
+            }
+            let ascii_idx = matched.unwrap_or((mean * last).round() as u8);
+            cells.push(Cell {
+                ascii_idx,
+                color: sum.map(|c| (c / lit.max(1)) as u8),
+                bg: None,
+            });
         }
     }
-    RleFrame {
-        width: w as u16,
-        runs,
+    RleFrame::from_cells(cols, &cells)
+}
+
+/// Paints each pixel as the background of its cell. With a `ramp`, the
+/// character for the pixel's brightness goes on top in black or white,
+/// otherwise the cell is left blank. The foreground only changes with the
+/// glyph, so runs break only where the background or the glyph does.
+pub fn convert_image_to_background(img: &RgbImage, ramp: Option<&[char]>) -> RleFrame {
+    let (w, h) = img.dimensions();
+    let last = ramp.map_or(0.0, |ramp| ramp.len().saturating_sub(1) as f32);
+    let mut cells = Vec::with_capacity((w * h) as usize);
+    for px in img.pixels() {
+        let cell = match ramp {
+            Some(_) => {
+                let intensity = luminance(px.0);
+                Cell {
+                    ascii_idx: (intensity * last).round() as u8,
+                    color: if intensity > 0.5 { [0; 3] } else { [255; 3] },
+                    bg: Some(px.0),
+                }
+            }
+            None => Cell {
+                ascii_idx: 0,
+                color: [0; 3],
+                bg: Some(px.0),
+            },
+        };
+        cells.push(cell);
     }
+    RleFrame::from_cells(w, &cells)
 }
 
-pub fn process_single_frame(path: &Path, size: (u16, u16)) -> Result<RleFrame, AppError> {
-    debug!("Processing frame: {}", path.display());
+/// Runs a Sobel filter over the luminance and lets the strong edge pixels in
+/// each `EDGE_CELL_SIZE` square vote on the direction of the edge. Cells with
+/// too few edge pixels get the ramp character for their average brightness.
+pub fn convert_image_to_edges(img: &RgbImage, ramp: &[char]) -> RleFrame {
+    let (w, h) = img.dimensions();
+    let (cols, rows) = (w / EDGE_CELL_SIZE, h / EDGE_CELL_SIZE);
+    let luma: Vec<f32> = img.pixels().map(|px| luminance(px.0)).collect();
+    let at = |x: i64, y: i64| {
+        let x = x.clamp(0, w as i64 - 1) as usize;
+        let y = y.clamp(0, h as i64 - 1) as usize;
+        luma[y * w as usize + x]
+    };
+    let last = ramp.len().saturating_sub(1) as f32;
+    let block_len = EDGE_CELL_SIZE * EDGE_CELL_SIZE;
 
-    let img = image::open(path).map_err(|e| {
-        error!("Failed to open image at {}: {}", path.display(), e);
-        AppError::Image {
-            source: e,
-            context: Some(path.display().to_string()),
+    let mut cells = Vec::with_capacity((cols * rows) as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            let mut votes = [0u32; EDGE_GLYPHS.len()];
+            let mut sum = [0u32; 3];
+            let mut brightness = 0.0;
+            for dy in 0..EDGE_CELL_SIZE {
+                for dx in 0..EDGE_CELL_SIZE {
+                    let (px, py) = (col * EDGE_CELL_SIZE + dx, row * EDGE_CELL_SIZE + dy);
+                    let rgb = img.get_pixel(px, py).0;
+                    for c in 0..3 {
+                        sum[c] += rgb[c] as u32;
+                    }
+                    brightness += luminance(rgb);
+
+                    let (x, y) = (px as i64, py as i64);
+                    let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                        - at(x - 1, y - 1)
+                        - 2.0 * at(x - 1, y)
+                        - at(x - 1, y + 1);
+                    let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                        - at(x - 1, y - 1)
+                        - 2.0 * at(x, y - 1)
+                        - at(x + 1, y - 1);
+                    if gx.hypot(gy) >= EDGE_MIN_MAGNITUDE {
+                        votes[edge_direction(gx, gy)] += 1;
+                    }
+                }
+            }
+
+            let edge = votes
+                .iter()
+                .enumerate()
+                .max_by_key(|&(_, &n)| n)
+                .filter(|&(_, &n)| n >= EDGE_MIN_PIXELS)
+                .map(|(i, _)| i);
+            let ascii_idx = match edge {
+                Some(i) => (ramp.len() + i) as u8,
+                None => (brightness / block_len as f32 * last).round() as u8,
+            };
+            cells.push(Cell {
+                ascii_idx,
+                color: sum.map(|c| (c / block_len) as u8),
+                bg: None,
+            });
         }
-    })?;
+    }
+    RleFrame::from_cells(cols, &cells)
+}
+
+/// Index into `EDGE_GLYPHS` for an edge with the luminance gradient
+/// (`gx`, `gy`), where `gy` grows downwards. The edge runs across the gradient.
+fn edge_direction(gx: f32, gy: f32) -> usize {
+    let angle = gy.atan2(gx).to_degrees().rem_euclid(180.0);
+    match angle {
+        a if !(22.5..157.5).contains(&a) => 0,
+        a if a < 67.5 => 2,
+        a if a < 112.5 && gy < 0.0 => 4,
+        a if a < 112.5 => 1,
+        _ => 3,
+    }
+}
 
-    let img = resize_and_center(&img, size.0, size.1);
-    Ok(convert_image_to_ascii(&img))
+pub fn convert_image(img: &RgbImage, settings: &RenderSettings) -> RleFrame {
+    let frame = match settings.mode {
+        RenderMode::Ascii => convert_image_to_ascii(img, &settings.ramp, settings.dither),
+        RenderMode::HalfBlock => convert_image_to_half_blocks(img),
+        RenderMode::Braille => convert_image_to_braille(img),
+        RenderMode::GlyphMatch => convert_image_to_matched_glyphs(img, &settings.ramp),
+        RenderMode::Edges => convert_image_to_edges(img, &settings.ramp),
+        RenderMode::Background => convert_image_to_background(img, None),
+        RenderMode::BackgroundAscii => convert_image_to_background(img, Some(&settings.ramp)),
+    };
+    if settings.monochrome {
+        frame.without_colors()
+    } else {
+        settings.quantization.apply(frame)
+    }
 }
 
-pub fn process_frames_parallel(
-    paths: &[PathBuf],
+/// Converts a batch of decoded frames and their timestamps in parallel,
+/// preserving their order.
+pub fn convert_frames_parallel(
+    batch: Vec<(RgbImage, Duration)>,
     size: (u16, u16),
-) -> Result<Vec<RleFrame>, AppError> {
-    info!("Processing {} frames", paths.len());
-
-    let pb = Mutex::new(
-        ProgressBar::new(paths.len() as u64)
-            .with_style(
-                ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
-                    .unwrap()
-                    .progress_chars("=> ")
+    settings: &RenderSettings,
+) -> Vec<RleFrame> {
+    let adjustments = settings.adjustments.prepare();
+    batch
+        .into_par_iter()
+        .map(|(img, pts)| {
+            let img =
+                resize_and_center(&DynamicImage::ImageRgb8(img), size.0, size.1, settings.mode);
+            let img = adjustments.apply(img);
+            RleFrame {
+                pts,
+                ..convert_image(&img, settings)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CLASSIC_RAMP, LEGACY_RAMP};
+    use proptest::prelude::*;
+
+    /// Colors the generated images are drawn from. With a two-character ramp
+    /// only white is bright enough for the second character.
+    const COLORS: [[u8; 3]; 4] = [[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 0, 255]];
+    const RAMP: [char; 2] = [' ', '#'];
+
+    fn expected_cell(color: [u8; 3]) -> Cell {
+        Cell {
+            ascii_idx: (color == [255, 255, 255]) as u8,
+            color,
+            bg: None,
+        }
+    }
+
+    /// Checks the invariants runs are written with: no empty runs and none
+    /// that continue past the end of a row.
+    fn assert_layout(frame: &RleFrame) {
+        let width = frame.width as u64;
+        let mut total = 0u64;
+        for run in &frame.runs {
+            assert_ne!(run.count, 0, "run of zero cells");
+            assert!(
+                total % width + run.count as u64 <= width,
+                "run crosses the end of row {}",
+                total / width
+            );
+            total += run.count as u64;
+        }
+        assert_eq!(total % width, 0);
+        assert_eq!(frame.validate(), Ok(()));
+    }
+
+    fn image(width: u32, height: u32, colors: &[usize]) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            Rgb(COLORS[colors[(y * width + x) as usize]])
+        })
+    }
+
+    fn image_strategy(
+        widths: std::ops::Range<u32>,
+        heights: std::ops::Range<u32>,
+    ) -> impl Strategy<Value = (u32, u32, Vec<usize>)> {
+        (widths, heights).prop_flat_map(|(w, h)| {
+            (
+                Just(w),
+                Just(h),
+                prop::collection::vec(0..COLORS.len(), (w * h) as usize),
             )
-            .with_message("Processing frames"),
-    );
+        })
+    }
 
-    let results: Result<Vec<_>, _> = paths
-        .par_iter()
-        .map(|path| {
-            let result = process_single_frame(path, size);
-            if let Ok(pb_lock) = pb.lock() {
-                pb_lock.inc(1);
-            }
-            result
+    proptest! {
+        #[test]
+        fn ascii_frames_round_trip((w, h, colors) in image_strategy(1..64, 1..16)) {
+            let img = image(w, h, &colors);
+            let frame = convert_image_to_ascii(&img, &RAMP, Dither::None);
+            assert_layout(&frame);
+            let expected: Vec<Cell> = img.pixels().map(|px| expected_cell(px.0)).collect();
+            prop_assert_eq!(frame.width, w);
+            prop_assert_eq!(frame.cells(), expected);
+        }
+
+        #[test]
+        fn cells_round_trip((w, h, colors) in image_strategy(1..64, 1..16)) {
+            let cells: Vec<Cell> = colors.iter().map(|&c| expected_cell(COLORS[c])).collect();
+            let frame = RleFrame::from_cells(w, &cells);
+            assert_layout(&frame);
+            prop_assert!(frame.runs.len() as u32 >= h);
+            prop_assert_eq!(frame.cells(), cells);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        #[test]
+        fn wide_frames_round_trip(
+            (w, h, colors) in image_strategy(u16::MAX as u32 - 2..u16::MAX as u32 * 2 + 3, 1..3)
+        ) {
+            let img = image(w, h, &colors);
+            let frame = convert_image_to_ascii(&img, &RAMP, Dither::None);
+            assert_layout(&frame);
+            let expected: Vec<Cell> = img.pixels().map(|px| expected_cell(px.0)).collect();
+            prop_assert_eq!(frame.cells(), expected);
+        }
+
+        #[test]
+        fn single_color_rows_split_at_row_ends(
+            w in 1..u16::MAX as u32 * 2 + 3,
+            h in 2u32..4,
+            color in 0..COLORS.len(),
+        ) {
+            let img = image(w, h, &vec![color; (w * h) as usize]);
+            let frame = convert_image_to_ascii(&img, &RAMP, Dither::None);
+            assert_layout(&frame);
+            // Each row is its own runs, split only where a run would overflow
+            let runs_per_row = w.div_ceil(u16::MAX as u32);
+            prop_assert_eq!(frame.runs.len() as u32, runs_per_row * h);
+            prop_assert_eq!(frame.cells(), vec![expected_cell(COLORS[color]); (w * h) as usize]);
+        }
+    }
+
+    /// Two frames of the same size, the second sharing a random part of its
+    /// cells with the first.
+    fn frame_pair_strategy() -> impl Strategy<Value = (Vec<Cell>, Vec<Cell>)> {
+        (1usize..600).prop_flat_map(|len| {
+            (
+                prop::collection::vec(0..COLORS.len(), len),
+                prop::collection::vec(prop::option::weighted(0.3, 0..COLORS.len()), len),
+            )
+                .prop_map(|(first, changes)| {
+                    let previous: Vec<Cell> =
+                        first.iter().map(|&c| expected_cell(COLORS[c])).collect();
+                    let next = previous
+                        .iter()
+                        .zip(&changes)
+                        .map(|(cell, change)| match change {
+                            Some(c) => expected_cell(COLORS[*c]),
+                            None => *cell,
+                        })
+                        .collect();
+                    (previous, next)
+                })
         })
-        .collect();
+    }
 
-    if let Ok(pb_lock) = pb.lock() {
-        pb_lock.finish_with_message("Frame processing complete");
+    proptest! {
+        #[test]
+        fn delta_turns_previous_frame_into_next((previous, next) in frame_pair_strategy()) {
+            let delta = DeltaFrame::between(&previous, &next);
+            let mut cells = previous.clone();
+            prop_assert!(delta.apply(&mut cells));
+            prop_assert_eq!(cells, next);
+        }
+
+        #[test]
+        fn delta_of_identical_frames_is_empty((previous, _) in frame_pair_strategy()) {
+            let delta = DeltaFrame::between(&previous, &previous);
+            prop_assert!(delta.spans.is_empty());
+        }
+    }
+
+    #[test]
+    fn delta_rejects_shorter_frame() {
+        let previous = vec![expected_cell(COLORS[0]); 8];
+        let mut next = previous.clone();
+        next[7] = expected_cell(COLORS[1]);
+        let delta = DeltaFrame::between(&previous, &next);
+        assert!(!delta.apply(&mut previous[..4].to_vec()));
+    }
+
+    fn run(count: u16) -> RleRun {
+        RleRun {
+            ascii_idx: 0,
+            color: [0; 3],
+            bg: None,
+            count,
+        }
+    }
+
+    fn frame(width: u32, counts: &[u16]) -> RleFrame {
+        RleFrame {
+            width,
+            runs: counts.iter().map(|&c| run(c)).collect(),
+            pts: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn validate_rejects_zero_count_run() {
+        let err = frame(4, &[2, 0, 2]).validate().unwrap_err();
+        assert!(err.contains("zero cells"), "{}", err);
+    }
+
+    #[test]
+    fn validate_rejects_run_crossing_row_end() {
+        let err = frame(4, &[3, 3, 2]).validate().unwrap_err();
+        assert!(err.contains("crosses the end of row 0"), "{}", err);
     }
 
-    results
+    #[test]
+    fn validate_rejects_partial_last_row() {
+        let err = frame(4, &[4, 2]).validate().unwrap_err();
+        assert!(err.contains("do not fill rows"), "{}", err);
+    }
+
+    #[test]
+    fn validate_rejects_too_many_cells() {
+        let rows = (MAX_FRAME_CELLS / u16::MAX as u64 + 1) as usize;
+        let frame = frame(u16::MAX as u32, &vec![u16::MAX; rows]);
+        let err = frame.validate().unwrap_err();
+        assert!(err.contains("more than"), "{}", err);
+    }
+
+    #[test]
+    fn validate_accepts_empty_frame() {
+        assert_eq!(frame(0, &[]).validate(), Ok(()));
+        assert!(frame(0, &[1]).validate().is_err());
+    }
+
+    /// Matches a single `GLYPH_WIDTH`x`GLYPH_HEIGHT` block against `ramp`
+    /// and returns the character picked for it.
+    fn match_block(block: RgbImage, ramp: &[char]) -> char {
+        let cells = convert_image_to_matched_glyphs(&block, ramp).cells();
+        assert_eq!(cells.len(), 1);
+        ramp[cells[0].ascii_idx as usize]
+    }
+
+    #[test]
+    fn glyph_match_picks_bar_for_vertical_edge() {
+        let ramp: Vec<char> = LEGACY_RAMP.chars().collect();
+        let block = RgbImage::from_fn(GLYPH_WIDTH, GLYPH_HEIGHT, |x, _| {
+            if x == GLYPH_WIDTH / 2 {
+                Rgb([255; 3])
+            } else {
+                Rgb([0; 3])
+            }
+        });
+        assert_eq!(match_block(block, &ramp), '|');
+    }
+
+    #[test]
+    fn glyph_match_picks_ramp_intensity_for_flat_block() {
+        let ramp: Vec<char> = CLASSIC_RAMP.chars().collect();
+        for (level, expected) in [(0, ' '), (128, '+'), (255, '@')] {
+            let block = RgbImage::from_pixel(GLYPH_WIDTH, GLYPH_HEIGHT, Rgb([level; 3]));
+            assert_eq!(match_block(block, &ramp), expected, "level {}", level);
+        }
+    }
 }
@@ -0,0 +1,138 @@
+use crate::config::{BLOCKS_RAMP, CLASSIC_RAMP, DENSE_RAMP, DIGITS_RAMP};
+use crate::error::AppError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Built-in ramps by the names `--charset` accepts.
+const BUILTIN_RAMPS: [(&str, &str); 4] = [
+    ("classic", CLASSIC_RAMP),
+    ("blocks", BLOCKS_RAMP),
+    ("dense", DENSE_RAMP),
+    ("digits", DIGITS_RAMP),
+];
+
+/// Character ramp for the ASCII render mode, either built in or read from a
+/// file holding the characters in order from dark to bright.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Charset {
+    Classic,
+    Blocks,
+    Dense,
+    Digits,
+    File(PathBuf),
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    /// Anything other than a built-in name has to look like a path, so a
+    /// misspelled name is reported as such rather than as a missing file.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "classic" => Charset::Classic,
+            "blocks" => Charset::Blocks,
+            "dense" => Charset::Dense,
+            "digits" => Charset::Digits,
+            _ if s.contains(['/', std::path::MAIN_SEPARATOR]) || Path::new(s).is_file() => {
+                Charset::File(PathBuf::from(s))
+            }
+            _ => {
+                return Err(format!(
+                    "unknown charset '{}', expected {} or a path to a file",
+                    s,
+                    builtin_names()
+                ));
+            }
+        })
+    }
+}
+
+impl Charset {
+    pub fn load(&self) -> Result<Vec<char>, AppError> {
+        let ramp: Vec<char> = match self {
+            Charset::Classic => CLASSIC_RAMP.chars().collect(),
+            Charset::Blocks => BLOCKS_RAMP.chars().collect(),
+            Charset::Dense => DENSE_RAMP.chars().collect(),
+            Charset::Digits => DIGITS_RAMP.chars().collect(),
+            Charset::File(path) => {
+                let contents = fs::read_to_string(path).map_err(|e| {
+                    AppError::InvalidCharset(format!(
+                        "cannot read {}: {} (built-in charsets are {})",
+                        path.display(),
+                        e,
+                        builtin_names()
+                    ))
+                })?;
+                // Leading spaces are part of the ramp, only the final newline is not
+                contents.trim_end_matches(['\r', '\n']).chars().collect()
+            }
+        };
+
+        if ramp.is_empty() {
+            return Err(AppError::InvalidCharset(
+                "ramp must contain at least one character".to_string(),
+            ));
+        }
+        if ramp.len() > u8::MAX as usize + 1 {
+            return Err(AppError::InvalidCharset(format!(
+                "ramp has {} characters, at most {} are supported",
+                ramp.len(),
+                u8::MAX as usize + 1
+            )));
+        }
+        if let Some(c) = ramp.iter().find(|c| c.is_control()) {
+            return Err(AppError::InvalidCharset(format!(
+                "ramp contains control character {:?}",
+                c
+            )));
+        }
+        Ok(ramp)
+    }
+}
+
+/// Name of the built-in ramp matching `ramp`, if any.
+pub fn builtin_name(ramp: &[char]) -> Option<&'static str> {
+    BUILTIN_RAMPS
+        .into_iter()
+        .find(|(_, builtin)| builtin.chars().eq(ramp.iter().copied()))
+        .map(|(name, _)| name)
+}
+
+fn builtin_names() -> String {
+    BUILTIN_RAMPS.map(|(name, _)| name).join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_paths_are_told_apart() {
+        assert_eq!("dense".parse(), Ok(Charset::Dense));
+        assert_eq!(
+            "ramps/mine.txt".parse(),
+            Ok(Charset::File(PathBuf::from("ramps/mine.txt")))
+        );
+        let err = "clasic".parse::<Charset>().unwrap_err();
+        assert!(
+            err.contains("'clasic'") && err.contains("classic, blocks"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn unreadable_file_names_path_and_builtins() {
+        let err = Charset::File(PathBuf::from("missing/ramp.txt"))
+            .load()
+            .unwrap_err();
+        let message = err.to_string();
+        assert!(matches!(err, AppError::InvalidCharset(_)));
+        assert!(
+            message.contains("missing/ramp.txt") && message.contains("dense, digits"),
+            "{}",
+            message
+        );
+    }
+}
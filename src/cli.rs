@@ -1,5 +1,15 @@
+use crate::adjust::Levels;
+use crate::ascii::RenderMode;
+use crate::charset::Charset;
+use crate::color::ColorMode;
+use crate::config::{DEFAULT_KEYFRAME_INTERVAL, MAX_SPEED, MIN_SPEED};
+use crate::dither::Dither;
+use crate::error::AppError;
+use crate::quantize::Quantize;
+use crate::video::TimeRange;
 use clap::Parser;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -14,13 +24,232 @@ pub struct CliArgs {
     #[arg(long, action = clap::ArgAction::SetTrue)]
     pub regenerate: bool,
 
-    #[arg(long, action = clap::ArgAction::SetTrue)]
+    /// Same as --color 256
+    #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "color")]
     pub compat: bool,
 
+    #[arg(long, value_enum, default_value_t = ColorMode::TrueColor)]
+    pub color: ColorMode,
+
+    /// Reduce colors before encoding so similar neighbours share a run
+    #[arg(long, value_enum, default_value_t = Quantize::None)]
+    pub quantize: Quantize,
+
+    /// How far --quantize may move each color channel (0-255)
+    #[arg(long, default_value_t = 8)]
+    pub quantize_tolerance: u8,
+
+    /// File with the 16 base colors of the terminal theme, one hex color per line
+    #[arg(long)]
+    pub palette: Option<PathBuf>,
+
     #[arg(long, action = clap::ArgAction::SetTrue)]
     pub loop_video: bool,
+
+    /// Position to start playing from, in seconds or as [hh:]mm:ss
+    #[arg(long, value_parser = parse_time)]
+    pub start: Option<Duration>,
+
+    /// Position to stop playing at, in seconds or as [hh:]mm:ss
+    #[arg(long, value_parser = parse_time, conflicts_with = "duration")]
+    pub end: Option<Duration>,
+
+    /// How long to play from --start, in seconds or as [hh:]mm:ss
+    #[arg(long, value_parser = parse_time, conflicts_with = "end")]
+    pub duration: Option<Duration>,
+
+    #[arg(long, value_enum, default_value_t = RenderMode::Ascii)]
+    pub mode: RenderMode,
+
+    /// classic, blocks, dense, digits, or a path to a file with characters from dark to bright
+    #[arg(long, default_value = "dense")]
+    pub charset: Charset,
+
+    /// Dithers the characters of the ascii mode, and the colors of every mode
+    /// with --color 256, 16 or gray
+    #[arg(long, value_enum, default_value_t = Dither::None)]
+    pub dither: Dither,
+
+    #[arg(long, default_value_t = 1.0, value_parser = parse_positive)]
+    pub gamma: f32,
+
+    #[arg(long, default_value_t = 1.0, value_parser = parse_non_negative)]
+    pub contrast: f32,
+
+    /// Offset from -1.0 (black) to 1.0 (white)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_parser = parse_brightness)]
+    pub brightness: f32,
+
+    #[arg(long, default_value_t = 1.0, value_parser = parse_non_negative)]
+    pub saturation: f32,
+
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub equalize: bool,
+
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub invert: bool,
+
+    /// Input black and white points, as black:white or one pair per channel (r,g,b)
+    #[arg(long)]
+    pub levels: Option<Levels>,
+
+    #[arg(long, default_value_t = DEFAULT_KEYFRAME_INTERVAL, value_parser = clap::value_parser!(u32).range(1..))]
+    pub keyframe_interval: u32,
+
+    /// Convert the video to this frame rate, dropping or repeating frames
+    #[arg(long, value_parser = parse_positive)]
+    pub fps: Option<f32>,
+
+    /// With --fps, blend neighbouring frames instead of dropping or repeating them
+    #[arg(long, action = clap::ArgAction::SetTrue, requires = "fps")]
+    pub fps_blend: bool,
+
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+    pub speed: f32,
+
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+    pub av_offset: i64,
+}
+
+impl CliArgs {
+    /// The part of the video to play, from --start to --end or for
+    /// --duration.
+    pub fn time_range(&self) -> Result<TimeRange, AppError> {
+        let start = self.start.unwrap_or_default();
+        TimeRange::new(start, self.end.or(self.duration.map(|d| start + d)))
+    }
+}
+
+fn parse_speed(s: &str) -> Result<f32, String> {
+    let speed: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if (MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        Ok(speed)
+    } else {
+        Err(format!(
+            "speed must be between {} and {}",
+            MIN_SPEED, MAX_SPEED
+        ))
+    }
+}
+
+fn parse_positive(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err("value must be greater than 0".to_string())
+    }
+}
+
+fn parse_non_negative(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if value >= 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err("value must not be negative".to_string())
+    }
+}
+
+fn parse_brightness(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if (-1.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err("brightness must be between -1 and 1".to_string())
+    }
+}
+
+/// Parses a position given in seconds, such as `90` or `12.5`, or as
+/// `mm:ss` or `hh:mm:ss` with optional fractional seconds.
+fn parse_time(s: &str) -> Result<Duration, String> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() > 3 {
+        return Err(format!("expected seconds or [hh:]mm:ss, got '{}'", s));
+    }
+    let mut seconds = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let value: f64 = part.trim().parse().map_err(|e| format!("{}", e))?;
+        let last = i == parts.len() - 1;
+        if !value.is_finite() || value < 0.0 {
+            return Err("time must not be negative".to_string());
+        }
+        if (!last && value.fract() != 0.0) || (i > 0 && value >= 60.0) {
+            return Err(format!("expected seconds or [hh:]mm:ss, got '{}'", s));
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Ok(Duration::from_secs_f64(seconds))
 }
 
 pub fn parse_args() -> CliArgs {
     CliArgs::parse()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, clap::Error> {
+        CliArgs::try_parse_from(["ascii-rs", "video.mp4"].iter().chain(args))
+    }
+
+    fn range(args: &[&str]) -> Result<TimeRange, AppError> {
+        parse(args).unwrap().time_range()
+    }
+
+    #[test]
+    fn times_are_parsed() {
+        assert_eq!(parse_time("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_time("1:30.5"), Ok(Duration::from_millis(90_500)));
+        assert_eq!(parse_time("1:02:03"), Ok(Duration::from_secs(3723)));
+        for time in ["-1", "1:60", "1.5:00", "1:2:3:4", "abc"] {
+            assert!(parse_time(time).is_err(), "{}", time);
+        }
+    }
+
+    #[test]
+    fn duration_and_end_conflict() {
+        for args in [
+            ["--duration", "5", "--end", "10"],
+            ["--end", "10", "--duration", "5"],
+        ] {
+            let err = parse(&args).unwrap_err();
+            assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn ranges_follow_start_end_and_duration() {
+        let secs = Duration::from_secs;
+        assert_eq!(range(&[]).unwrap(), TimeRange::default());
+        assert_eq!(
+            range(&["--start", "1:00", "--duration", "20"]).unwrap(),
+            TimeRange {
+                start: secs(60),
+                end: Some(secs(80)),
+            }
+        );
+        assert_eq!(
+            range(&["--end", "30"]).unwrap(),
+            TimeRange {
+                start: Duration::ZERO,
+                end: Some(secs(30)),
+            }
+        );
+    }
+
+    #[test]
+    fn end_must_follow_start() {
+        for args in [
+            ["--start", "20", "--end", "10"],
+            ["--start", "20", "--end", "20"],
+        ] {
+            assert!(
+                matches!(range(&args), Err(AppError::InvalidTimeRange(_))),
+                "{:?}",
+                args
+            );
+        }
+        assert!(range(&["--start", "20", "--duration", "0"]).is_err());
+    }
+}
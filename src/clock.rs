@@ -0,0 +1,350 @@
+use crate::config::MAX_CLOCK_DRIFT;
+use rodio::source::SeekError;
+use rodio::{Sample, Source};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use std::time::{Duration, Instant};
+
+/// Media time of the playhead. Unlike a plain `Instant`, it can be paused,
+/// moved to any position and run faster or slower than real time.
+///
+/// When audio is playing the clock follows the samples actually consumed by
+/// the output device. It runs on wall-clock time in between, and is moved to
+/// the audio position only when the two drift apart, since the device takes
+/// samples in blocks rather than at a steady pace.
+pub struct PlaybackClock {
+    anchor: Instant,
+    anchor_position: Duration,
+    paused: bool,
+    rate: f64,
+    audio: AudioClock,
+    /// How far the video lags behind the audio, in seconds. Negative values
+    /// show the video early.
+    av_offset: f64,
+}
+
+impl PlaybackClock {
+    /// A running clock starting at position zero, advancing `rate` seconds of
+    /// media per second of real time and following `audio` while it plays.
+    pub fn start(rate: f64, audio: AudioClock, av_offset_ms: i64) -> Self {
+        PlaybackClock {
+            anchor: Instant::now(),
+            anchor_position: Duration::ZERO,
+            paused: false,
+            rate,
+            audio,
+            av_offset: av_offset_ms as f64 / 1000.0,
+        }
+    }
+
+    /// Re-anchors the clock to the audio playback position if audio is
+    /// currently playing and the clock is more than `MAX_CLOCK_DRIFT` away
+    /// from it.
+    pub fn sync(&mut self) {
+        if self.paused {
+            return;
+        }
+        if let Some(audio) = self.audio.position() {
+            let target = (audio.as_secs_f64() - self.av_offset).max(0.0);
+            if (self.position().as_secs_f64() - target).abs() > MAX_CLOCK_DRIFT.as_secs_f64() {
+                self.anchor = Instant::now();
+                self.anchor_position = Duration::from_secs_f64(target);
+            }
+        }
+    }
+
+    /// Audio position that corresponds to the video `position`.
+    pub fn audio_position_for(&self, position: Duration) -> Duration {
+        Duration::from_secs_f64((position.as_secs_f64() + self.av_offset).max(0.0))
+    }
+
+    pub fn position(&self) -> Duration {
+        if self.paused {
+            self.anchor_position
+        } else {
+            self.anchor_position + self.anchor.elapsed().mul_f64(self.rate)
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        if !self.paused {
+            self.anchor_position = self.position();
+            self.paused = true;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.anchor = Instant::now();
+            self.paused = false;
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn set_rate(&mut self, rate: f64) {
+        self.anchor_position = self.position();
+        self.anchor = Instant::now();
+        self.rate = rate;
+    }
+
+    pub fn seek(&mut self, position: Duration) {
+        self.anchor = Instant::now();
+        self.anchor_position = position;
+    }
+
+    /// Real time until the clock reaches `position`, or `None` while paused.
+    pub fn time_until(&self, position: Duration) -> Option<Duration> {
+        if self.paused {
+            None
+        } else {
+            Some(position.saturating_sub(self.position()).div_f64(self.rate))
+        }
+    }
+}
+
+/// Playback position of the audio currently queued on the sink, shared with
+/// the `TrackedSource` that feeds it.
+#[derive(Clone, Default)]
+pub struct AudioClock {
+    state: Arc<AudioClockState>,
+}
+
+#[derive(Default)]
+struct AudioClockState {
+    samples: AtomicU64,
+    samples_per_second: AtomicU64,
+    active: AtomicBool,
+}
+
+impl AudioClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps `source` so the samples pulled from it advance this clock. The
+    /// count restarts from zero, so only one source should be queued at a time.
+    pub fn track<S>(&self, source: S) -> TrackedSource<S>
+    where
+        S: Source,
+        S::Item: Sample,
+    {
+        let samples_per_second = source.sample_rate() as u64 * source.channels() as u64;
+        self.state.samples.store(0, Ordering::Relaxed);
+        self.state
+            .samples_per_second
+            .store(samples_per_second, Ordering::Relaxed);
+        self.state
+            .active
+            .store(samples_per_second > 0, Ordering::Relaxed);
+        TrackedSource {
+            inner: source,
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    /// Stops following the audio, e.g. after a seek it could not perform.
+    pub fn detach(&self) {
+        self.state.active.store(false, Ordering::Relaxed);
+    }
+
+    /// Position of the last sample handed to the output, or `None` when no
+    /// audio is playing.
+    pub fn position(&self) -> Option<Duration> {
+        if !self.state.active.load(Ordering::Relaxed) {
+            return None;
+        }
+        let samples_per_second = self.state.samples_per_second.load(Ordering::Relaxed);
+        let samples = self.state.samples.load(Ordering::Relaxed);
+        Some(Duration::from_secs_f64(
+            samples as f64 / samples_per_second as f64,
+        ))
+    }
+}
+
+/// Audio source that counts the samples consumed by the mixer.
+pub struct TrackedSource<S> {
+    inner: S,
+    state: Arc<AudioClockState>,
+}
+
+impl<S> Iterator for TrackedSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.inner.next();
+        if sample.is_some() {
+            self.state.samples.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.state.active.store(false, Ordering::Relaxed);
+        }
+        sample
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for TrackedSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        let channels = self.inner.channels() as u64;
+        let frames = (pos.as_secs_f64() * self.inner.sample_rate() as f64) as u64;
+        self.state
+            .samples
+            .store(frames * channels, Ordering::Relaxed);
+        self.state.active.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const CHANNELS: u16 = 2;
+    const SAMPLE_RATE: u32 = 1000;
+
+    /// Four seconds of stereo audio.
+    fn source() -> SamplesBuffer<i16> {
+        SamplesBuffer::new(CHANNELS, SAMPLE_RATE, vec![0i16; 8000])
+    }
+
+    fn close_to(actual: Duration, expected: Duration) -> bool {
+        actual.abs_diff(expected) < Duration::from_millis(5)
+    }
+
+    #[test]
+    fn audio_clock_counts_consumed_samples() {
+        let clock = AudioClock::new();
+        assert_eq!(clock.position(), None);
+
+        let mut tracked = clock.track(source());
+        assert_eq!(clock.position(), Some(Duration::ZERO));
+        tracked.by_ref().take(500).for_each(drop);
+        assert_eq!(clock.position(), Some(Duration::from_millis(250)));
+
+        tracked.by_ref().for_each(drop);
+        assert_eq!(clock.position(), None);
+    }
+
+    #[test]
+    fn audio_clock_follows_seeks() {
+        let clock = AudioClock::new();
+        let mut tracked = clock.track(source());
+        tracked.by_ref().for_each(drop);
+
+        tracked.try_seek(Duration::from_millis(1500)).unwrap();
+        assert_eq!(clock.position(), Some(Duration::from_millis(1500)));
+        tracked.by_ref().take(200).for_each(drop);
+        assert_eq!(clock.position(), Some(Duration::from_millis(1600)));
+
+        clock.detach();
+        assert_eq!(clock.position(), None);
+    }
+
+    #[test]
+    fn tracking_a_new_source_restarts_the_count() {
+        let clock = AudioClock::new();
+        clock.track(source()).take(1000).for_each(drop);
+        assert_eq!(clock.position(), Some(Duration::from_millis(500)));
+        let _tracked = clock.track(source());
+        assert_eq!(clock.position(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn positive_offset_shows_video_later() {
+        let audio = AudioClock::new();
+        let mut tracked = audio.track(source());
+        let mut clock = PlaybackClock::start(1.0, audio.clone(), 200);
+        assert_eq!(
+            clock.audio_position_for(Duration::from_secs(1)),
+            Duration::from_millis(1200)
+        );
+
+        tracked.try_seek(Duration::from_millis(1200)).unwrap();
+        clock.sync();
+        assert!(close_to(clock.position(), Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn negative_offset_shows_video_earlier() {
+        let audio = AudioClock::new();
+        let mut tracked = audio.track(source());
+        let mut clock = PlaybackClock::start(1.0, audio.clone(), -200);
+        assert_eq!(
+            clock.audio_position_for(Duration::from_secs(1)),
+            Duration::from_millis(800)
+        );
+
+        tracked.try_seek(Duration::from_millis(800)).unwrap();
+        clock.sync();
+        assert!(close_to(clock.position(), Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn small_drift_is_left_to_the_wall_clock() {
+        let audio = AudioClock::new();
+        let mut tracked = audio.track(source());
+        let mut clock = PlaybackClock::start(1.0, audio.clone(), 0);
+        clock.seek(Duration::from_secs(1));
+
+        tracked.try_seek(Duration::from_millis(1020)).unwrap();
+        clock.sync();
+        assert!(close_to(clock.position(), Duration::from_secs(1)));
+
+        tracked.try_seek(Duration::from_millis(1100)).unwrap();
+        clock.sync();
+        assert!(close_to(clock.position(), Duration::from_millis(1100)));
+    }
+
+    #[test]
+    fn paused_clock_ignores_audio() {
+        let audio = AudioClock::new();
+        let mut tracked = audio.track(source());
+        let mut clock = PlaybackClock::start(1.0, audio.clone(), 0);
+        clock.seek(Duration::from_secs(2));
+        clock.pause();
+
+        tracked.try_seek(Duration::from_secs(3)).unwrap();
+        let paused_at = clock.position();
+        clock.sync();
+        assert_eq!(clock.position(), paused_at);
+        assert!(close_to(paused_at, Duration::from_secs(2)));
+        assert_eq!(clock.time_until(Duration::from_secs(3)), None);
+    }
+}
@@ -1,19 +1,215 @@
-pub fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
-    // If the color is a shade of gray
-    if r == g && g == b {
-        if r < 8 {
-            16 // black
-        } else if r > 248 {
-            231 // white
+use crate::dither::{Dither, ErrorDiffuser, bayer};
+use crate::palette::Palette;
+use std::ops::RangeInclusive;
+
+/// How colors are sent to the terminal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorMode {
+    /// 24-bit RGB escapes.
+    #[default]
+    #[value(name = "truecolor")]
+    TrueColor,
+    /// The 256-color palette, for terminals with limited color support.
+    #[value(name = "256")]
+    Ansi256,
+    /// The 16 basic ANSI colors.
+    #[value(name = "16")]
+    Ansi16,
+    /// The 24 shades of the 256-color grayscale ramp.
+    Gray,
+    /// No color escapes at all, only glyphs. Not for the modes drawn with
+    /// background colors.
+    None,
+}
+
+impl ColorMode {
+    /// Palette entries the mode draws from, or `None` when it does not use
+    /// the palette.
+    fn candidates(self, palette: &Palette) -> Option<RangeInclusive<u8>> {
+        match self {
+            // The base colors vary between themes, so they are only used
+            // when the theme is known
+            ColorMode::Ansi256 if palette.is_themed() => Some(0..=255),
+            ColorMode::Ansi256 => Some(16..=255),
+            ColorMode::Ansi16 => Some(0..=15),
+            ColorMode::Gray => Some(232..=255),
+            ColorMode::TrueColor | ColorMode::None => None,
+        }
+    }
+
+    /// Whether colors are matched to palette entries, which `--dither` can
+    /// spread the error of.
+    pub fn uses_palette(self) -> bool {
+        matches!(
+            self,
+            ColorMode::Ansi256 | ColorMode::Ansi16 | ColorMode::Gray
+        )
+    }
+
+    /// Typical distance between neighbouring palette colors, which scales the
+    /// ordered dithering offsets.
+    fn step(self) -> f32 {
+        match self {
+            ColorMode::Ansi16 => 255.0 / 2.0,
+            ColorMode::Gray => 10.0,
+            ColorMode::TrueColor | ColorMode::Ansi256 | ColorMode::None => 255.0 / 5.0,
+        }
+    }
+}
+
+/// Bits per channel the lookup table is indexed with.
+const LUT_BITS: u32 = 5;
+
+/// Levels per channel in the lookup table, spread evenly from 0 to 255 so
+/// that black and white are matched exactly.
+const LUT_MAX_LEVEL: usize = (1 << LUT_BITS) - 1;
+
+/// Chooses palette entries for a color mode by how they will be displayed,
+/// comparing colors in OKLab so near-grays and dark colors keep their hue.
+/// The nearest entry is precomputed for a grid of colors.
+pub struct PaletteMapper {
+    mode: ColorMode,
+    palette: Palette,
+    lut: Vec<u8>,
+}
+
+impl PaletteMapper {
+    pub fn new(mode: ColorMode, palette: &Palette) -> Self {
+        let lut = match mode.candidates(palette) {
+            Some(candidates) => build_lut(palette, candidates),
+            None => Vec::new(),
+        };
+        PaletteMapper {
+            mode,
+            palette: palette.clone(),
+            lut,
+        }
+    }
+
+    pub fn mode(&self) -> ColorMode {
+        self.mode
+    }
+
+    /// Palette entry closest to `rgb`. Only meaningful for modes that draw
+    /// from the palette.
+    fn nearest(&self, rgb: [u8; 3]) -> u8 {
+        self.lut[lut_index(rgb)]
+    }
+
+    /// Maps a frame of colors, row by row, to palette entries, spreading the
+    /// difference to the displayed color according to `dither`.
+    pub fn map_dithered(&self, colors: &[[u8; 3]], width: usize, dither: Dither) -> Vec<u8> {
+        if width == 0 || self.lut.is_empty() {
+            return Vec::new();
+        }
+        let step = self.mode.step();
+        let mut diffuser = ErrorDiffuser::<3>::new(dither, width);
+        let mut indices = Vec::with_capacity(colors.len());
+        for (y, row) in colors.chunks(width).enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                let offset = match dither {
+                    Dither::None => [0.0; 3],
+                    Dither::Ordered => [bayer(x, y) * step; 3],
+                    _ => diffuser.error_at(x),
+                };
+                let target: [f32; 3] = std::array::from_fn(|c| color[c] as f32 + offset[c]);
+                let idx = self.nearest(target.map(|v| v.round().clamp(0.0, 255.0) as u8));
+                let shown = self.palette.rgb(idx);
+                diffuser.diffuse(x, std::array::from_fn(|c| target[c] - shown[c] as f32));
+                indices.push(idx);
+            }
+            diffuser.next_row();
+        }
+        indices
+    }
+}
+
+fn lut_index(rgb: [u8; 3]) -> usize {
+    rgb.iter().fold(0, |idx, &v| {
+        (idx << LUT_BITS) | ((v as usize * LUT_MAX_LEVEL + 127) / 255)
+    })
+}
+
+/// Nearest palette entry among `candidates` for every color of the lookup
+/// table.
+fn build_lut(palette: &Palette, candidates: RangeInclusive<u8>) -> Vec<u8> {
+    let entries: Vec<(u8, [f32; 3])> = candidates
+        .map(|idx| (idx, oklab(palette.rgb(idx))))
+        .collect();
+    (0..1usize << (3 * LUT_BITS))
+        .map(|i| {
+            let rgb: [u8; 3] = std::array::from_fn(|c| {
+                let level = (i >> (LUT_BITS * (2 - c as u32))) & LUT_MAX_LEVEL;
+                ((level * 255 + LUT_MAX_LEVEL / 2) / LUT_MAX_LEVEL) as u8
+            });
+            let lab = oklab(rgb);
+            entries
+                .iter()
+                .min_by(|(_, a), (_, b)| distance(&lab, a).total_cmp(&distance(&lab, b)))
+                .map(|&(idx, _)| idx)
+                .unwrap()
+        })
+        .collect()
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]).powi(2)).sum()
+}
+
+/// Converts an sRGB color to OKLab.
+fn oklab(rgb: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|v| {
+        let v = v as f64 / 255.0;
+        if v <= 0.04045 {
+            v / 12.92
         } else {
-            // gray index
-            232 + ((r as f32 - 8.0) / 10.0).round() as u8
+            ((v + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    let lab = [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ];
+    lab.map(|v| v as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Solarized Dark, a theme whose base colors are far from xterm's.
+    const SOLARIZED: [[u8; 3]; 16] = [
+        [0x07, 0x36, 0x42],
+        [0xdc, 0x32, 0x2f],
+        [0x85, 0x99, 0x00],
+        [0xb5, 0x89, 0x00],
+        [0x26, 0x8b, 0xd2],
+        [0xd3, 0x36, 0x82],
+        [0x2a, 0xa1, 0x98],
+        [0xee, 0xe8, 0xd5],
+        [0x00, 0x2b, 0x36],
+        [0xcb, 0x4b, 0x16],
+        [0x58, 0x6e, 0x75],
+        [0x65, 0x7b, 0x83],
+        [0x83, 0x94, 0x96],
+        [0x6c, 0x71, 0xc4],
+        [0x93, 0xa1, 0xa1],
+        [0xfd, 0xf6, 0xe3],
+    ];
+
+    #[test]
+    fn themed_entries_map_to_themselves() {
+        let palette = Palette::themed(SOLARIZED);
+        for mode in [ColorMode::Ansi16, ColorMode::Ansi256] {
+            let mapper = PaletteMapper::new(mode, &palette);
+            for idx in 0..16 {
+                let mapped = mapper.map_dithered(&[palette.rgb(idx)], 1, Dither::None);
+                assert_eq!(mapped, [idx], "{:?} entry {}", mode, idx);
+            }
         }
-    } else {
-        // Not a gray, so map to the 6x6x6 color cube
-        let r_idx = (r as f32 / 255.0 * 5.0).round() as u8;
-        let g_idx = (g as f32 / 255.0 * 5.0).round() as u8;
-        let b_idx = (b as f32 / 255.0 * 5.0).round() as u8;
-        16 + 36 * r_idx + 6 * g_idx + b_idx
     }
 }
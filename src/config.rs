@@ -1,18 +1,76 @@
-use lazy_static::lazy_static;
-
 pub const AUTHOR: &str = "minhcrafters";
 
-const ASCII_STR: &str = " .:,;'_\"^<>-!~=)(|j?}{}][ti+l7v1%yrfcJ32uIC$zwo96sgnaT5qpkyVOL40&mG8*xhedbZUSAPQFDXWK#RNEHBM@";
+// Character ramps for `--charset`, ordered from dark to bright
+pub const CLASSIC_RAMP: &str = " .:-=+*#%@";
+pub const BLOCKS_RAMP: &str = " \u{2591}\u{2592}\u{2593}\u{2588}";
+pub const DENSE_RAMP: &str =
+    " .:,;'_\"^<>-!~=)(|j?}{][ti+l7v1%yrfcJ32uIC$zwo96sgnaT5qpkVOL40&mG8*xhedbZUSAPQFDXWK#RNEHBM@";
+pub const DIGITS_RAMP: &str = " 1723549608";
 
-lazy_static! {
-    pub static ref ASCII_CHARS: Vec<char> = ASCII_STR.chars().collect();
-}
+/// Ramp of caches written before the ramp was stored alongside the frames.
+pub const LEGACY_RAMP: &str = " .:,;'_\"^<>-!~=)(|j?}{}][ti+l7v1%yrfcJ32uIC$zwo96sgnaT5qpkyVOL40&mG8*xhedbZUSAPQFDXWK#RNEHBM@";
 
 pub const CHAR_ASPECT_RATIO: f32 = 2.0;
 
-pub const ACSV_VERSION: u8 = 1;
+/// Smallest brightness spread within a 2x4 block for its Braille dots to
+/// follow the block's own average instead of a fixed midpoint.
+pub const BRAILLE_MIN_CONTRAST: f32 = 0.1;
+
+/// Smallest brightness spread within a cell for glyph matching to look at its
+/// shape rather than just its brightness.
+pub const GLYPH_MATCH_MIN_CONTRAST: f32 = 0.08;
+
+/// Side of the square of pixels behind each cell in the edge mode.
+pub const EDGE_CELL_SIZE: u32 = 4;
+
+/// Sobel gradient magnitude, on luminance from 0.0 to 1.0, from which a pixel
+/// counts as part of an edge.
+pub const EDGE_MIN_MAGNITUDE: f32 = 0.5;
+
+/// Edge pixels a cell needs before it is drawn as a line.
+pub const EDGE_MIN_PIXELS: u32 = 4;
+
+/// Most colors a median-cut palette may have in one frame, even when that
+/// leaves some colors further than the tolerance from their entry.
+pub const MAX_QUANTIZE_COLORS: usize = 256;
+
+/// Most cells a frame read from a cache may expand to, well above a 4K
+/// character grid.
+pub const MAX_FRAME_CELLS: u64 = 1 << 25;
+
+pub const ACSV_VERSION: u8 = 3;
+pub const ACSV_LEGACY_VERSION: u8 = 1;
 pub const ACSV_MAGIC: &[u8; 4] = b"ACSV";
+pub const ACSV_FOOTER_MAGIC: &[u8; 4] = b"ACSI";
+
+pub const FRAME_BATCH_SIZE: usize = 64;
+pub const FRAME_WINDOW_SIZE: usize = 64;
+
+pub const DEFAULT_KEYFRAME_INTERVAL: u32 = 150;
+pub const DELTA_MERGE_GAP: usize = 4;
 
 pub const ZSTD_COMPRESSION_LEVEL: i32 = 12;
 
+pub const PREBUFFER_DURATION: std::time::Duration = std::time::Duration::from_secs(3);
+/// How far conversion may run ahead of the playhead before it waits.
+pub const BUFFER_AHEAD: std::time::Duration = std::time::Duration::from_secs(10);
+/// How long frames stay in memory after they were shown, for seeking back
+/// without reading the cache.
+pub const BUFFER_BEHIND: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Speeds `[` and `]` step through, spanning the range `--speed` accepts.
+/// A speed between two steps moves to the next step in either direction.
+pub const SPEED_STEPS: &[f32] = &[
+    0.1, 0.25, 0.5, 0.75, 1.0, 1.25, 1.5, 2.0, 3.0, 4.0, 6.0, 8.0,
+];
+pub const MIN_SPEED: f32 = SPEED_STEPS[0];
+pub const MAX_SPEED: f32 = SPEED_STEPS[SPEED_STEPS.len() - 1];
+
+/// How far the playback clock may drift from the audio before it is moved
+/// back to it.
+pub const MAX_CLOCK_DRIFT: std::time::Duration = std::time::Duration::from_millis(30);
+
+pub const SEEK_STEP: std::time::Duration = std::time::Duration::from_secs(5);
+pub const PAUSED_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(15);
+
 pub const METRICS_UPDATE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
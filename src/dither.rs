@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+/// How values are spread between quantization steps (ramp characters or
+/// palette colors) to avoid banding in gradients.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Dither {
+    /// Round to the nearest step.
+    #[default]
+    None,
+    /// Offset each position by a 4x4 Bayer threshold.
+    Ordered,
+    /// Diffuse the whole rounding error to the right and below.
+    FloydSteinberg,
+    /// Diffuse three quarters of the rounding error, keeping more contrast.
+    Atkinson,
+}
+
+impl Dither {
+    pub fn name(self) -> &'static str {
+        match self {
+            Dither::None => "none",
+            Dither::Ordered => "ordered",
+            Dither::FloydSteinberg => "floyd-steinberg",
+            Dither::Atkinson => "atkinson",
+        }
+    }
+
+    /// Neighbours that receive part of a pixel's error, as (dx, dy, weight).
+    fn kernel(self) -> &'static [(isize, usize, f32)] {
+        match self {
+            Dither::None | Dither::Ordered => &[],
+            Dither::FloydSteinberg => &[
+                (1, 0, 7.0 / 16.0),
+                (-1, 1, 3.0 / 16.0),
+                (0, 1, 5.0 / 16.0),
+                (1, 1, 1.0 / 16.0),
+            ],
+            Dither::Atkinson => &[
+                (1, 0, 1.0 / 8.0),
+                (2, 0, 1.0 / 8.0),
+                (-1, 1, 1.0 / 8.0),
+                (0, 1, 1.0 / 8.0),
+                (1, 1, 1.0 / 8.0),
+                (0, 2, 1.0 / 8.0),
+            ],
+        }
+    }
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Ordered dithering threshold for a position, in the range -0.5 to 0.5.
+pub fn bayer(x: usize, y: usize) -> f32 {
+    (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5
+}
+
+/// Error diffusion over one image, visited row by row from left to right.
+/// Each frame gets its own diffuser, so the result does not depend on how
+/// frames are spread across threads.
+pub struct ErrorDiffuser<const N: usize> {
+    kernel: &'static [(isize, usize, f32)],
+    width: usize,
+    /// Pending error for the current row and the two below it.
+    rows: [Vec<[f32; N]>; 3],
+}
+
+impl<const N: usize> ErrorDiffuser<N> {
+    pub fn new(dither: Dither, width: usize) -> Self {
+        ErrorDiffuser {
+            kernel: dither.kernel(),
+            width,
+            rows: std::array::from_fn(|_| vec![[0.0; N]; width]),
+        }
+    }
+
+    /// Error carried into column `x` of the current row.
+    pub fn error_at(&self, x: usize) -> [f32; N] {
+        self.rows[0][x]
+    }
+
+    /// Spreads the rounding `error` made at column `x` of the current row.
+    pub fn diffuse(&mut self, x: usize, error: [f32; N]) {
+        for &(dx, dy, weight) in self.kernel {
+            let Some(nx) = x.checked_add_signed(dx).filter(|&nx| nx < self.width) else {
+                continue;
+            };
+            let target = &mut self.rows[dy][nx];
+            for c in 0..N {
+                target[c] += error[c] * weight;
+            }
+        }
+    }
+
+    pub fn next_row(&mut self) {
+        self.rows.rotate_left(1);
+        self.rows[2].fill([0.0; N]);
+    }
+}
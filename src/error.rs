@@ -1,4 +1,3 @@
-use image::ImageError;
 use rodio::{PlayError, decoder::DecoderError};
 use std::num::{ParseFloatError, ParseIntError};
 use std::path::PathBuf;
@@ -13,12 +12,6 @@ pub enum AppError {
         context: Option<String>,
     },
 
-    #[error("Image processing error: {source}")]
-    Image {
-        source: ImageError,
-        context: Option<String>,
-    },
-
     #[error("Audio playback error: {source}")]
     AudioPlayback {
         source: PlayError,
@@ -49,6 +42,12 @@ pub enum AppError {
     #[error("Could not determine video properties (resolution, fps) for: {0}")]
     VideoMetadata(PathBuf),
 
+    #[error("Invalid metadata for {0}: {1}")]
+    InvalidMetadata(PathBuf, String),
+
+    #[error("Invalid time range: {0}")]
+    InvalidTimeRange(String),
+
     #[error("Invalid ACSV file: {0}")]
     InvalidAcsv(String),
 
@@ -71,7 +70,6 @@ pub enum AppError {
     },
 
     #[error("Failed to parse float: {source}")]
-    #[allow(dead_code)]
     ParseFloat {
         source: ParseFloatError,
         context: Option<String>,
@@ -95,6 +93,15 @@ pub enum AppError {
         context: Option<String>,
     },
 
+    #[error("Invalid character set: {0}")]
+    InvalidCharset(String),
+
+    #[error("Invalid palette: {0}")]
+    InvalidPalette(String),
+
+    #[error("Incompatible options: {0}")]
+    IncompatibleOptions(String),
+
     #[error("Frame processing failed")]
     FrameProcessing,
 
@@ -107,9 +114,6 @@ pub enum AppError {
     #[error("Could not create output directory: {0}")]
     CreateDir(PathBuf, std::io::Error),
 
-    #[error("Could not clean up frame directory: {0}")]
-    CleanupFrames(PathBuf, std::io::Error),
-
     #[error("Could not get system information: {0}")]
     SystemInfo(String),
 }
@@ -0,0 +1,134 @@
+use lazy_static::lazy_static;
+
+// 5x8 glyphs of the printable ASCII range (' ' to '~') from the X11
+// misc-fixed "5x8" font (5x8.bdf, shipped with X.Org as part of
+// font-misc-misc). Its header declares the font public domain, so the bitmaps
+// are copied here without a license file. Each byte is one row, top to
+// bottom, with the leftmost pixel in bit 4.
+pub const GLYPH_WIDTH: u32 = 5;
+pub const GLYPH_HEIGHT: u32 = 8;
+
+const FIRST_GLYPH: char = ' ';
+
+#[rustfmt::skip]
+const GLYPHS: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04, 0x00], // '!'
+    [0x00, 0x0a, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a, 0x00], // '#'
+    [0x04, 0x0e, 0x14, 0x0e, 0x05, 0x0e, 0x04, 0x00], // '$'
+    [0x00, 0x08, 0x0a, 0x04, 0x0a, 0x02, 0x00, 0x00], // '%'
+    [0x08, 0x14, 0x14, 0x08, 0x14, 0x14, 0x0a, 0x00], // '&'
+    [0x00, 0x04, 0x04, 0x04, 0x00, 0x00, 0x00, 0x00], // "'"
+    [0x00, 0x04, 0x08, 0x08, 0x08, 0x08, 0x04, 0x00], // '('
+    [0x00, 0x08, 0x04, 0x04, 0x04, 0x04, 0x08, 0x00], // ')'
+    [0x00, 0x00, 0x12, 0x0c, 0x1e, 0x0c, 0x12, 0x00], // '*'
+    [0x00, 0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x04, 0x08], // ','
+    [0x00, 0x00, 0x00, 0x00, 0x1e, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x0e, 0x04], // '.'
+    [0x00, 0x02, 0x02, 0x04, 0x08, 0x10, 0x10, 0x00], // '/'
+    [0x00, 0x04, 0x0a, 0x0a, 0x0a, 0x0a, 0x04, 0x00], // '0'
+    [0x00, 0x04, 0x0c, 0x04, 0x04, 0x04, 0x0e, 0x00], // '1'
+    [0x00, 0x0c, 0x12, 0x02, 0x0c, 0x10, 0x1e, 0x00], // '2'
+    [0x00, 0x1e, 0x04, 0x0c, 0x02, 0x12, 0x0c, 0x00], // '3'
+    [0x00, 0x04, 0x0c, 0x14, 0x1e, 0x04, 0x04, 0x00], // '4'
+    [0x00, 0x1e, 0x10, 0x1c, 0x02, 0x12, 0x0c, 0x00], // '5'
+    [0x00, 0x0c, 0x10, 0x1c, 0x12, 0x12, 0x0c, 0x00], // '6'
+    [0x00, 0x1e, 0x02, 0x04, 0x04, 0x08, 0x08, 0x00], // '7'
+    [0x00, 0x0c, 0x12, 0x0c, 0x12, 0x12, 0x0c, 0x00], // '8'
+    [0x00, 0x0c, 0x12, 0x12, 0x0e, 0x02, 0x0c, 0x00], // '9'
+    [0x00, 0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00], // ':'
+    [0x00, 0x00, 0x06, 0x06, 0x00, 0x06, 0x04, 0x08], // ';'
+    [0x00, 0x02, 0x04, 0x08, 0x08, 0x04, 0x02, 0x00], // '<'
+    [0x00, 0x00, 0x00, 0x1e, 0x00, 0x1e, 0x00, 0x00], // '='
+    [0x00, 0x08, 0x04, 0x02, 0x02, 0x04, 0x08, 0x00], // '>'
+    [0x00, 0x04, 0x0a, 0x02, 0x04, 0x00, 0x04, 0x00], // '?'
+    [0x06, 0x09, 0x13, 0x15, 0x15, 0x12, 0x08, 0x06], // '@'
+    [0x00, 0x0c, 0x12, 0x12, 0x1e, 0x12, 0x12, 0x00], // 'A'
+    [0x00, 0x1c, 0x12, 0x1c, 0x12, 0x12, 0x1c, 0x00], // 'B'
+    [0x00, 0x0c, 0x12, 0x10, 0x10, 0x12, 0x0c, 0x00], // 'C'
+    [0x00, 0x1c, 0x12, 0x12, 0x12, 0x12, 0x1c, 0x00], // 'D'
+    [0x00, 0x1e, 0x10, 0x1c, 0x10, 0x10, 0x1e, 0x00], // 'E'
+    [0x00, 0x1e, 0x10, 0x1c, 0x10, 0x10, 0x10, 0x00], // 'F'
+    [0x00, 0x0c, 0x12, 0x10, 0x16, 0x12, 0x0c, 0x00], // 'G'
+    [0x00, 0x12, 0x12, 0x1e, 0x12, 0x12, 0x12, 0x00], // 'H'
+    [0x00, 0x0e, 0x04, 0x04, 0x04, 0x04, 0x0e, 0x00], // 'I'
+    [0x00, 0x0e, 0x04, 0x04, 0x04, 0x14, 0x08, 0x00], // 'J'
+    [0x00, 0x12, 0x14, 0x18, 0x14, 0x14, 0x12, 0x00], // 'K'
+    [0x00, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1e, 0x00], // 'L'
+    [0x00, 0x12, 0x1e, 0x1e, 0x12, 0x12, 0x12, 0x00], // 'M'
+    [0x00, 0x12, 0x1a, 0x1e, 0x16, 0x16, 0x12, 0x00], // 'N'
+    [0x00, 0x0c, 0x12, 0x12, 0x12, 0x12, 0x0c, 0x00], // 'O'
+    [0x00, 0x1c, 0x12, 0x12, 0x1c, 0x10, 0x10, 0x00], // 'P'
+    [0x00, 0x0c, 0x12, 0x12, 0x1a, 0x16, 0x0c, 0x02], // 'Q'
+    [0x00, 0x1c, 0x12, 0x12, 0x1c, 0x12, 0x12, 0x00], // 'R'
+    [0x00, 0x0c, 0x12, 0x08, 0x04, 0x12, 0x0c, 0x00], // 'S'
+    [0x00, 0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x00], // 'T'
+    [0x00, 0x12, 0x12, 0x12, 0x12, 0x12, 0x0c, 0x00], // 'U'
+    [0x00, 0x12, 0x12, 0x12, 0x12, 0x0c, 0x0c, 0x00], // 'V'
+    [0x00, 0x12, 0x12, 0x12, 0x1e, 0x1e, 0x12, 0x00], // 'W'
+    [0x00, 0x12, 0x12, 0x0c, 0x0c, 0x12, 0x12, 0x00], // 'X'
+    [0x00, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04, 0x00], // 'Y'
+    [0x00, 0x1e, 0x02, 0x04, 0x08, 0x10, 0x1e, 0x00], // 'Z'
+    [0x00, 0x0e, 0x08, 0x08, 0x08, 0x08, 0x0e, 0x00], // '['
+    [0x00, 0x10, 0x10, 0x08, 0x04, 0x02, 0x02, 0x00], // '\\'
+    [0x00, 0x0e, 0x02, 0x02, 0x02, 0x02, 0x0e, 0x00], // ']'
+    [0x00, 0x04, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1e], // '_'
+    [0x00, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x00, 0x0e, 0x12, 0x12, 0x0e, 0x00], // 'a'
+    [0x00, 0x10, 0x10, 0x1c, 0x12, 0x12, 0x1c, 0x00], // 'b'
+    [0x00, 0x00, 0x00, 0x06, 0x08, 0x08, 0x06, 0x00], // 'c'
+    [0x00, 0x02, 0x02, 0x0e, 0x12, 0x12, 0x0e, 0x00], // 'd'
+    [0x00, 0x00, 0x00, 0x0c, 0x16, 0x18, 0x0c, 0x00], // 'e'
+    [0x00, 0x04, 0x0a, 0x08, 0x1c, 0x08, 0x08, 0x00], // 'f'
+    [0x00, 0x00, 0x00, 0x0c, 0x12, 0x0e, 0x02, 0x0c], // 'g'
+    [0x00, 0x10, 0x10, 0x1c, 0x12, 0x12, 0x12, 0x00], // 'h'
+    [0x00, 0x04, 0x00, 0x0c, 0x04, 0x04, 0x0e, 0x00], // 'i'
+    [0x00, 0x02, 0x00, 0x02, 0x02, 0x02, 0x0a, 0x04], // 'j'
+    [0x00, 0x10, 0x10, 0x12, 0x1c, 0x12, 0x12, 0x00], // 'k'
+    [0x00, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e, 0x00], // 'l'
+    [0x00, 0x00, 0x00, 0x1a, 0x15, 0x15, 0x15, 0x00], // 'm'
+    [0x00, 0x00, 0x00, 0x1c, 0x12, 0x12, 0x12, 0x00], // 'n'
+    [0x00, 0x00, 0x00, 0x0c, 0x12, 0x12, 0x0c, 0x00], // 'o'
+    [0x00, 0x00, 0x00, 0x1c, 0x12, 0x1c, 0x10, 0x10], // 'p'
+    [0x00, 0x00, 0x00, 0x0e, 0x12, 0x0e, 0x02, 0x02], // 'q'
+    [0x00, 0x00, 0x00, 0x14, 0x1a, 0x10, 0x10, 0x00], // 'r'
+    [0x00, 0x00, 0x00, 0x06, 0x0c, 0x02, 0x0c, 0x00], // 's'
+    [0x00, 0x08, 0x08, 0x1c, 0x08, 0x0a, 0x04, 0x00], // 't'
+    [0x00, 0x00, 0x00, 0x12, 0x12, 0x12, 0x0e, 0x00], // 'u'
+    [0x00, 0x00, 0x00, 0x0a, 0x0a, 0x0a, 0x04, 0x00], // 'v'
+    [0x00, 0x00, 0x00, 0x11, 0x15, 0x15, 0x0a, 0x00], // 'w'
+    [0x00, 0x00, 0x00, 0x12, 0x0c, 0x0c, 0x12, 0x00], // 'x'
+    [0x00, 0x00, 0x00, 0x12, 0x12, 0x0e, 0x12, 0x0c], // 'y'
+    [0x00, 0x00, 0x00, 0x1e, 0x04, 0x08, 0x1e, 0x00], // 'z'
+    [0x06, 0x08, 0x04, 0x18, 0x04, 0x08, 0x06, 0x00], // '{'
+    [0x00, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x00], // '|'
+    [0x18, 0x04, 0x08, 0x06, 0x08, 0x04, 0x18, 0x00], // '}'
+    [0x00, 0x0a, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+lazy_static! {
+    /// Every glyph as a bit mask with pixel (x, y) in bit `y * GLYPH_WIDTH + x`.
+    static ref GLYPH_MASKS: Vec<u64> = GLYPHS
+        .iter()
+        .map(|rows| {
+            rows.iter().enumerate().fold(0u64, |mask, (y, row)| {
+                (0..GLYPH_WIDTH).fold(mask, |mask, x| {
+                    if row & (1 << (GLYPH_WIDTH - 1 - x)) != 0 {
+                        mask | 1 << (y as u32 * GLYPH_WIDTH + x)
+                    } else {
+                        mask
+                    }
+                })
+            })
+        })
+        .collect();
+}
+
+/// Pixel mask of `c`, or `None` if the font has no glyph for it.
+pub fn glyph_mask(c: char) -> Option<u64> {
+    let idx = (c as u32).checked_sub(FIRST_GLYPH as u32)? as usize;
+    GLYPH_MASKS.get(idx).copied()
+}
@@ -1,17 +1,38 @@
+mod adjust;
 mod ascii;
+mod charset;
 mod cli;
+mod clock;
 mod color;
 mod config;
+mod dither;
 mod error;
+mod font;
 mod logging;
 mod metrics;
+mod palette;
+mod pipeline;
 mod playback;
+mod quantize;
 mod storage;
 mod terminal;
 mod utils;
 mod video;
 
-use crate::{ascii::RleFrame, error::AppError, terminal::TerminalManager, video::VideoInfo};
+use crate::{
+    adjust::Adjustments,
+    ascii::{RenderMode, RenderSettings},
+    color::ColorMode,
+    config::LEGACY_RAMP,
+    dither::Dither,
+    error::AppError,
+    palette::Palette,
+    pipeline::{FrameBuffer, FrameSource, Pipeline},
+    quantize::Quantization,
+    storage::{CacheHeader, CachedFrames},
+    terminal::TerminalManager,
+    video::{Resample, VideoInfo},
+};
 use log::LevelFilter;
 use std::{
     io,
@@ -52,6 +73,7 @@ fn run_app() -> Result<(), AppError> {
     })?;
 
     let args = cli::parse_args();
+    let range = args.time_range()?;
 
     let video_path = args.video;
 
@@ -72,7 +94,82 @@ fn run_app() -> Result<(), AppError> {
         );
     }
 
-    let video_info = VideoInfo::analyze(&video_path, terminal_size)?;
+    let color = if args.compat {
+        ColorMode::Ansi256
+    } else {
+        args.color
+    };
+    if color == ColorMode::None && args.mode.uses_background() {
+        return Err(AppError::IncompatibleOptions(format!(
+            "the {} mode draws with background colors, which --color none leaves out",
+            args.mode.name()
+        )));
+    }
+    // Dithering picks the characters of the ascii mode and the colors of the
+    // palette modes; anything else would silently ignore it
+    if args.dither != Dither::None && args.mode != RenderMode::Ascii && !color.uses_palette() {
+        return Err(AppError::IncompatibleOptions(format!(
+            "--dither only affects the colors of the {} mode, and only with --color 256, 16 or gray",
+            args.mode.name()
+        )));
+    }
+    let palette = match &args.palette {
+        Some(path) => Palette::load(path)?,
+        None => Palette::default(),
+    };
+    let render_settings = RenderSettings {
+        mode: args.mode,
+        ramp: args.charset.load()?,
+        dither: args.dither,
+        adjustments: Adjustments {
+            levels: args.levels,
+            equalize: args.equalize,
+            gamma: args.gamma,
+            contrast: args.contrast,
+            brightness: args.brightness,
+            saturation: args.saturation,
+            invert: args.invert,
+        },
+        monochrome: color == ColorMode::None,
+        quantization: Quantization {
+            method: args.quantize,
+            tolerance: args.quantize_tolerance,
+        },
+    };
+
+    if render_settings.glyphs().len() > u8::MAX as usize + 1 {
+        return Err(AppError::InvalidCharset(format!(
+            "the {} mode needs a shorter ramp, it draws at most {} characters",
+            render_settings.mode.name(),
+            u8::MAX as usize + 1
+        )));
+    }
+    // Glyph matching can only pick characters the bundled font can draw
+    if render_settings.mode == RenderMode::GlyphMatch
+        && render_settings
+            .ramp
+            .iter()
+            .filter(|&&c| font::glyph_mask(c).is_some())
+            .count()
+            < 2
+    {
+        return Err(AppError::InvalidCharset(
+            "the glyph-match mode needs at least 2 printable ASCII characters in the ramp"
+                .to_string(),
+        ));
+    }
+    let resample = args.fps.map(|fps| Resample {
+        fps,
+        blend: args.fps_blend,
+    });
+    let mut cache_key = render_settings.cache_key();
+    if let Some(resample) = &resample {
+        if !cache_key.is_empty() {
+            cache_key.push('-');
+        }
+        cache_key.push_str(&resample.cache_key());
+    }
+    let video_info = VideoInfo::analyze(&video_path, terminal_size, range, &cache_key)?;
     if global_stop_signal.load(Ordering::Relaxed) {
         return Err(AppError::Interrupted);
     }
@@ -83,19 +180,42 @@ fn run_app() -> Result<(), AppError> {
         return Err(AppError::Interrupted);
     }
 
-    let rle_frames: Vec<RleFrame>;
-    let mut cleanup_frames_dir = false;
-
-    if video_info.ascii_cache_path.exists() && !args.regenerate {
+    let cached_frames = if video_info.ascii_cache_path.exists() && !args.regenerate {
         log::info!(
             "Found existing cache file: {}",
             video_info.ascii_cache_path.display()
         );
 
-        match storage::load_ascii_frames(&video_info.ascii_cache_path) {
-            Ok(frames) => {
+        match storage::load_ascii_frames(&video_info.ascii_cache_path, video_info.frame_rate) {
+            Ok(CachedFrames::Indexed(reader)) if reader.header().grid != terminal_size => {
+                log::warn!(
+                    "Cache file {} was rendered for a {}x{} terminal. Regenerating frames.",
+                    video_info.ascii_cache_path.display(),
+                    reader.header().grid.0,
+                    reader.header().grid.1
+                );
+                None
+            }
+            Ok(CachedFrames::Indexed(reader)) if reader.len() > 0 => {
+                log::info!("Opened cache with {} frames.", reader.len());
+                let glyphs = reader.header().render.glyphs();
+                let frame_rate = reader.header().frame_rate;
+                Some((FrameSource::Cached(reader), glyphs, frame_rate))
+            }
+            Ok(CachedFrames::Legacy(frames)) if !frames.is_empty() => {
                 log::info!("Successfully loaded {} frames from cache.", frames.len());
-                rle_frames = frames;
+                Some((
+                    FrameSource::Buffered(Arc::new(FrameBuffer::from_frames(frames))),
+                    LEGACY_RAMP.chars().collect(),
+                    video_info.frame_rate,
+                ))
+            }
+            Ok(_) => {
+                log::warn!(
+                    "Cache file {} contains no frames. Regenerating frames.",
+                    video_info.ascii_cache_path.display()
+                );
+                None
             }
             Err(e) => {
                 log::warn!(
@@ -103,16 +223,7 @@ fn run_app() -> Result<(), AppError> {
                     video_info.ascii_cache_path.display(),
                     e
                 );
-                let frame_paths = video_info.extract_frames()?;
-                if global_stop_signal.load(Ordering::Relaxed) {
-                    return Err(AppError::Interrupted);
-                }
-                cleanup_frames_dir = true;
-                rle_frames = ascii::process_frames_parallel(&frame_paths, terminal_size)?;
-                if global_stop_signal.load(Ordering::Relaxed) {
-                    return Err(AppError::Interrupted);
-                }
-                storage::save_ascii_frames(&video_info.ascii_cache_path, &rle_frames)?;
+                None
             }
         }
     } else {
@@ -124,48 +235,73 @@ fn run_app() -> Result<(), AppError> {
                 video_info.ascii_cache_path.display()
             );
         }
+        None
+    };
 
-        let frame_paths = video_info.extract_frames()?;
-        if global_stop_signal.load(Ordering::Relaxed) {
-            return Err(AppError::Interrupted);
-        }
-        cleanup_frames_dir = true;
-        rle_frames = ascii::process_frames_parallel(&frame_paths, terminal_size)?;
-        if global_stop_signal.load(Ordering::Relaxed) {
-            return Err(AppError::Interrupted);
+    let (frames, glyphs, frame_rate, pipeline) = match cached_frames {
+        Some((frames, glyphs, frame_rate)) => (frames, glyphs, frame_rate, None),
+        None => {
+            let frame_stream = video_info.stream_frames(terminal_size, args.mode, resample)?;
+            let cache_header = CacheHeader {
+                grid: terminal_size,
+                frame_rate: resample.map_or(video_info.frame_rate, |r| r.fps),
+                keyframe_interval: args.keyframe_interval,
+                render: render_settings.clone(),
+            };
+            let expected_frames = match resample {
+                Some(r) => r.frame_count(video_info.duration),
+                None => video_info.total_frames,
+            };
+            let pipeline = Pipeline::spawn(
+                frame_stream,
+                terminal_size,
+                expected_frames as usize,
+                &video_info.ascii_cache_path,
+                &cache_header,
+                Arc::clone(&global_stop_signal),
+            )?;
+            (
+                FrameSource::Buffered(pipeline.buffer()),
+                render_settings.glyphs(),
+                cache_header.frame_rate,
+                Some(pipeline),
+            )
         }
-        storage::save_ascii_frames(&video_info.ascii_cache_path, &rle_frames)?;
-    }
-
-    if cleanup_frames_dir {
-        storage::cleanup_frame_directory(video_info.frames_dir.path())?;
-    }
-    if global_stop_signal.load(Ordering::Relaxed) {
-        return Err(AppError::Interrupted);
-    }
-
-    if rle_frames.is_empty() {
-        log::error!("No frames were generated or loaded. Cannot play.");
-        return Err(AppError::FrameProcessing);
-    }
-
-    log::info!("Prepared {} frames for playback", rle_frames.len());
+    };
 
     let metrics_monitor = metrics::MetricsMonitor::new()?;
 
     let mut player = playback::Player::new(
-        rle_frames,
+        frames,
         video_info.audio_path.clone(),
-        video_info.frame_rate,
+        frame_rate,
         terminal_manager,
         metrics_monitor,
-        args.compat,
-        args.loop_video,
+        playback::PlaybackOptions {
+            color,
+            loop_video: args.loop_video,
+            speed: args.speed,
+            av_offset_ms: args.av_offset,
+            glyphs,
+            dither: args.dither,
+            palette,
+        },
     )?;
 
     player.stop_signal = global_stop_signal;
 
     let play_result = player.play();
+    drop(player);
+
+    if let Some(pipeline) = pipeline {
+        pipeline.join()?;
+        if video_info.ascii_cache_path.exists() {
+            log::info!(
+                "Saved frames to cache: {}",
+                video_info.ascii_cache_path.display()
+            );
+        }
+    }
 
     play_result?;
 
@@ -0,0 +1,205 @@
+use crate::error::AppError;
+use std::fs;
+use std::path::Path;
+
+/// The xterm default colors for the 16 base entries.
+const XTERM_COLORS: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [205, 0, 0],
+    [0, 205, 0],
+    [205, 205, 0],
+    [0, 0, 238],
+    [205, 0, 205],
+    [0, 205, 205],
+    [229, 229, 229],
+    [127, 127, 127],
+    [255, 0, 0],
+    [0, 255, 0],
+    [255, 255, 0],
+    [92, 92, 255],
+    [255, 0, 255],
+    [0, 255, 255],
+    [255, 255, 255],
+];
+
+/// Levels of the 6x6x6 color cube in the 256-color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Colors the terminal shows for the entries of the 256-color palette. The
+/// 16 base colors depend on the terminal theme; without a theme file xterm's
+/// defaults are assumed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    base: [[u8; 3]; 16],
+    themed: bool,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            base: XTERM_COLORS,
+            themed: false,
+        }
+    }
+}
+
+impl Palette {
+    /// Reads the 16 base colors from a theme file. Each line holds a hex color
+    /// such as `#3465a4`, either on its own, in palette order, or after a key
+    /// naming the entry, as in `*.color4: #3465a4` or `color4 = "3465a4"`.
+    /// Other keys, blank lines, `[section]` headers and lines starting with
+    /// `!`, `;` or `//` are ignored, as are lines starting with `#` that are
+    /// not a color, such as the `#define` lines of Xresources files.
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        let contents = fs::read_to_string(path).map_err(|e| AppError::Io {
+            source: e,
+            context: Some(format!("Reading palette {}", path.display())),
+        })?;
+
+        let mut base: [Option<[u8; 3]>; 16] = [None; 16];
+        let mut next = 0;
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            let comment = line.starts_with(['!', ';'])
+                || line.starts_with("//")
+                || (line.starts_with('#') && parse_hex(line).is_none())
+                || (line.starts_with('[') && line.ends_with(']'));
+            if line.is_empty() || comment {
+                continue;
+            }
+            let invalid =
+                |reason: &str| AppError::InvalidPalette(format!("line {}: {}", number + 1, reason));
+
+            let (idx, value) = match line.split_once([':', '=']) {
+                Some((key, value)) => {
+                    let key = key.trim().to_ascii_lowercase();
+                    let Some(idx) = key
+                        .rsplit_once("color")
+                        .and_then(|(_, n)| n.parse::<usize>().ok())
+                    else {
+                        continue;
+                    };
+                    (idx, value)
+                }
+                None => {
+                    next += 1;
+                    (next - 1, line)
+                }
+            };
+            if idx >= base.len() {
+                // Entries past the base colors are fixed by the palette itself
+                continue;
+            }
+            let color = parse_hex(value).ok_or_else(|| invalid("expected a color like #rrggbb"))?;
+            base[idx] = Some(color);
+        }
+
+        let mut colors = [[0u8; 3]; 16];
+        for (idx, color) in base.iter().enumerate() {
+            colors[idx] = color.ok_or_else(|| {
+                AppError::InvalidPalette(format!("{} has no color {}", path.display(), idx))
+            })?;
+        }
+        Ok(Palette::themed(colors))
+    }
+
+    /// A palette with the base colors of the user's theme.
+    pub fn themed(base: [[u8; 3]; 16]) -> Self {
+        Palette { base, themed: true }
+    }
+
+    /// Whether the base colors come from the user's theme, so they can be
+    /// relied on when choosing colors.
+    pub fn is_themed(&self) -> bool {
+        self.themed
+    }
+
+    /// Color the terminal shows for an entry of the 256-color palette.
+    pub fn rgb(&self, idx: u8) -> [u8; 3] {
+        match idx {
+            0..=15 => self.base[idx as usize],
+            16..=231 => {
+                let i = idx - 16;
+                [
+                    CUBE_LEVELS[(i / 36) as usize],
+                    CUBE_LEVELS[(i / 6 % 6) as usize],
+                    CUBE_LEVELS[(i % 6) as usize],
+                ]
+            }
+            232..=255 => {
+                let v = 8 + 10 * (idx - 232);
+                [v, v, v]
+            }
+        }
+    }
+}
+
+fn parse_hex(value: &str) -> Option<[u8; 3]> {
+    let hex = value
+        .trim()
+        .trim_matches(|c| c == '"' || c == '\'' || c == ',')
+        .trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let mut rgb = [0u8; 3];
+    for (c, channel) in rgb.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[c * 2..c * 2 + 2], 16).ok()?;
+    }
+    Some(rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn load(contents: &str) -> Result<Palette, AppError> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        Palette::load(file.path())
+    }
+
+    /// The 16 colors the test themes define, `#000000` to `#0f0f0f`.
+    fn expected() -> Palette {
+        Palette::themed(std::array::from_fn(|i| [i as u8; 3]))
+    }
+
+    #[test]
+    fn list_of_colors_in_palette_order() {
+        let mut contents = "# my theme\n\n".to_string();
+        for i in 0..16 {
+            contents.push_str(&format!("#{:02x}{:02x}{:02x}\n", i, i, i));
+        }
+        assert_eq!(load(&contents).unwrap(), expected());
+    }
+
+    #[test]
+    fn xresources_keys_name_the_entries() {
+        let mut contents = String::from(
+            "! Xresources theme\n#define base00 #000000\n#include \"fonts\"\n*.foreground: #ffffff\n",
+        );
+        for i in (0..16).rev() {
+            contents.push_str(&format!("URxvt*color{}: #{:02x}{:02x}{:02x}\n", i, i, i, i));
+        }
+        assert_eq!(load(&contents).unwrap(), expected());
+    }
+
+    #[test]
+    fn sections_and_quoted_values_are_read() {
+        let mut contents = String::from("[colors]\n; generated\nbackground = \"000000\"\n");
+        for i in 0..16 {
+            contents.push_str(&format!("color{} = \"{:02x}{:02x}{:02x}\"\n", i, i, i, i));
+        }
+        assert_eq!(load(&contents).unwrap(), expected());
+    }
+
+    #[test]
+    fn bad_and_missing_colors_are_reported() {
+        let err = load("*.color0: #00000g\n").unwrap_err().to_string();
+        assert!(err.contains("line 1"), "{}", err);
+
+        let err = load("#000000\n#111111\n").unwrap_err().to_string();
+        assert!(err.contains("no color 2"), "{}", err);
+    }
+}
@@ -0,0 +1,593 @@
+use crate::ascii::{self, RenderSettings, RleFrame};
+use crate::config::{BUFFER_AHEAD, BUFFER_BEHIND, FRAME_BATCH_SIZE};
+use crate::error::AppError;
+use crate::storage::{CacheHeader, CacheReader, CacheWriter};
+use crate::video::FrameStream;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{
+    Arc, Condvar, Mutex,
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Sender},
+};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Frames shared between the converter and the player. The converter appends
+/// to it while the player reads behind it, so playback can start before the
+/// whole video has been converted.
+///
+/// Only a window around the frame the player shows is held in memory: the
+/// converter is held back once it is `max_ahead` frames past it, and frames
+/// more than `max_behind` before it are dropped. Dropped frames are read back
+/// from the cache file being written, once the writer has synced its index.
+pub struct FrameBuffer {
+    state: Mutex<BufferState>,
+    changed: Condvar,
+    expected_len: usize,
+    max_ahead: usize,
+    max_behind: usize,
+    history: Mutex<Option<CacheReader>>,
+}
+
+struct BufferState {
+    /// Frames from `first` on.
+    frames: VecDeque<Arc<RleFrame>>,
+    first: usize,
+    /// Frame the player is showing.
+    playhead: usize,
+    complete: bool,
+    failed: bool,
+}
+
+impl BufferState {
+    /// Number of frames converted so far, including dropped ones.
+    fn len(&self) -> usize {
+        self.first + self.frames.len()
+    }
+
+    fn get(&self, idx: usize) -> Option<&Arc<RleFrame>> {
+        self.frames.get(idx.checked_sub(self.first)?)
+    }
+
+    /// Drops the frames more than `max_behind` before the playhead.
+    fn trim(&mut self, max_behind: usize) {
+        let keep_from = self.playhead.saturating_sub(max_behind).min(self.len());
+        let dropped = keep_from.saturating_sub(self.first);
+        self.frames.drain(..dropped);
+        self.first += dropped;
+    }
+}
+
+impl FrameBuffer {
+    pub fn new(expected_len: usize, max_ahead: usize, max_behind: usize) -> Self {
+        FrameBuffer {
+            state: Mutex::new(BufferState {
+                frames: VecDeque::new(),
+                first: 0,
+                playhead: 0,
+                complete: false,
+                failed: false,
+            }),
+            changed: Condvar::new(),
+            expected_len,
+            max_ahead,
+            max_behind,
+            history: Mutex::new(None),
+        }
+    }
+
+    /// A buffer that already holds every frame, e.g. one loaded from cache.
+    pub fn from_frames(frames: Vec<RleFrame>) -> Self {
+        let expected_len = frames.len();
+        FrameBuffer {
+            state: Mutex::new(BufferState {
+                frames: frames.into_iter().map(Arc::new).collect(),
+                first: 0,
+                playhead: 0,
+                complete: true,
+                failed: false,
+            }),
+            changed: Condvar::new(),
+            expected_len,
+            max_ahead: usize::MAX,
+            max_behind: usize::MAX,
+            history: Mutex::new(None),
+        }
+    }
+
+    /// Appends converted frames, first waiting for the playhead while the
+    /// buffer is already `max_ahead` frames past it. Gives up once
+    /// `stop_signal` is set.
+    pub fn push(
+        &self,
+        frames: Vec<Arc<RleFrame>>,
+        stop_signal: &AtomicBool,
+    ) -> Result<(), AppError> {
+        let mut state = self.state.lock().unwrap();
+        while state.len() >= state.playhead.saturating_add(self.max_ahead) {
+            if stop_signal.load(Ordering::Relaxed) {
+                return Err(AppError::Interrupted);
+            }
+            // The stop signal does not notify, so check it now and then
+            (state, _) = self
+                .changed
+                .wait_timeout(state, Duration::from_millis(100))
+                .unwrap();
+        }
+        state.frames.extend(frames);
+        state.trim(self.max_behind);
+        drop(state);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Records the frame the player shows, which lets the converter continue
+    /// up to `max_ahead` frames past it.
+    pub fn set_playhead(&self, idx: usize) {
+        let mut state = self.state.lock().unwrap();
+        if state.playhead != idx {
+            state.playhead = idx;
+            state.trim(self.max_behind);
+            drop(state);
+            self.changed.notify_all();
+        }
+    }
+
+    /// Brings the reader for dropped frames up to date with the frames
+    /// `writer` has written.
+    pub fn sync_history(&self, writer: &mut CacheWriter) -> Result<(), AppError> {
+        writer.sync_reader(&mut self.history.lock().unwrap())
+    }
+
+    pub fn complete(&self) {
+        self.state.lock().unwrap().complete = true;
+        self.changed.notify_all();
+    }
+
+    pub fn fail(&self) {
+        self.state.lock().unwrap().failed = true;
+        self.changed.notify_all();
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().len()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state.lock().unwrap().complete
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.state.lock().unwrap().failed
+    }
+
+    /// Number of frames the video is expected to have once fully converted.
+    pub fn expected_len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        if state.complete {
+            state.len()
+        } else {
+            state.len().max(self.expected_len)
+        }
+    }
+
+    /// Oldest frame `get` can return. Frames dropped from memory count once
+    /// all of them can be read back from the cache.
+    pub fn first_available(&self) -> usize {
+        let first = self.state.lock().unwrap().first;
+        let history = self.history.lock().unwrap();
+        if history.as_ref().map_or(0, CacheReader::len) >= first {
+            0
+        } else {
+            first
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Result<Option<Arc<RleFrame>>, AppError> {
+        let state = self.state.lock().unwrap();
+        if idx >= state.first {
+            return Ok(state.get(idx).cloned());
+        }
+        drop(state);
+        match &*self.history.lock().unwrap() {
+            Some(history) => history.frame(idx),
+            None => Ok(None),
+        }
+    }
+
+    pub fn pts(&self, idx: usize) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        if idx >= state.first {
+            return state.get(idx).map(|f| f.pts);
+        }
+        drop(state);
+        self.history.lock().unwrap().as_ref()?.pts(idx)
+    }
+
+    /// Number of converted frames shown at or before `position`.
+    pub fn frames_until(&self, position: Duration) -> usize {
+        let state = self.state.lock().unwrap();
+        match state.frames.front() {
+            Some(front) if front.pts > position && state.first > 0 => {
+                let first = state.first;
+                drop(state);
+                match &*self.history.lock().unwrap() {
+                    Some(history) => history.frames_until(position).min(first),
+                    None => first,
+                }
+            }
+            _ => state.first + state.frames.partition_point(|f| f.pts <= position),
+        }
+    }
+
+    /// Blocks until at least `count` frames are converted, the producer has
+    /// stopped, or `timeout` elapses. Returns the number of converted frames.
+    pub fn wait_for(&self, count: usize, timeout: Duration) -> usize {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .changed
+            .wait_timeout_while(state, timeout, |s| {
+                s.len() < count && !s.complete && !s.failed
+            })
+            .unwrap();
+        state.len()
+    }
+}
+
+/// Where the player reads its frames from.
+pub enum FrameSource {
+    /// Frames held in memory, possibly still being produced by a `Pipeline`.
+    Buffered(Arc<FrameBuffer>),
+    /// Frames read on demand from an indexed cache file.
+    Cached(CacheReader),
+}
+
+impl FrameSource {
+    pub fn len(&self) -> usize {
+        match self {
+            FrameSource::Buffered(buffer) => buffer.len(),
+            FrameSource::Cached(reader) => reader.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_complete(&self) -> bool {
+        match self {
+            FrameSource::Buffered(buffer) => buffer.is_complete(),
+            FrameSource::Cached(_) => true,
+        }
+    }
+
+    pub fn is_failed(&self) -> bool {
+        match self {
+            FrameSource::Buffered(buffer) => buffer.is_failed(),
+            FrameSource::Cached(_) => false,
+        }
+    }
+
+    pub fn expected_len(&self) -> usize {
+        match self {
+            FrameSource::Buffered(buffer) => buffer.expected_len(),
+            FrameSource::Cached(reader) => reader.len(),
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Result<Option<Arc<RleFrame>>, AppError> {
+        match self {
+            FrameSource::Buffered(buffer) => buffer.get(idx),
+            FrameSource::Cached(reader) => reader.frame(idx),
+        }
+    }
+
+    /// Presentation time of a frame that is already available.
+    pub fn pts(&self, idx: usize) -> Option<Duration> {
+        match self {
+            FrameSource::Buffered(buffer) => buffer.pts(idx),
+            FrameSource::Cached(reader) => reader.pts(idx),
+        }
+    }
+
+    /// Number of available frames whose presentation time is at or before
+    /// `position`.
+    pub fn frames_until(&self, position: Duration) -> usize {
+        match self {
+            FrameSource::Buffered(buffer) => buffer.frames_until(position),
+            FrameSource::Cached(reader) => reader.frames_until(position),
+        }
+    }
+
+    pub fn wait_for(&self, count: usize, timeout: Duration) -> usize {
+        match self {
+            FrameSource::Buffered(buffer) => buffer.wait_for(count, timeout),
+            FrameSource::Cached(reader) => reader.len(),
+        }
+    }
+
+    /// Oldest frame that can be shown, e.g. after seeking back.
+    pub fn first_available(&self) -> usize {
+        match self {
+            FrameSource::Buffered(buffer) => buffer.first_available(),
+            FrameSource::Cached(_) => 0,
+        }
+    }
+
+    /// Tells a converter feeding the frames which one is on screen.
+    pub fn set_playhead(&self, idx: usize) {
+        if let FrameSource::Buffered(buffer) = self {
+            buffer.set_playhead(idx);
+        }
+    }
+}
+
+enum CacheMessage {
+    Frame(Arc<RleFrame>),
+    Finish,
+}
+
+/// Background conversion of a frame stream into a `FrameBuffer`, with the
+/// converted frames written to the ACSV cache as they are produced.
+pub struct Pipeline {
+    buffer: Arc<FrameBuffer>,
+    converter: JoinHandle<Result<(), AppError>>,
+    writer: JoinHandle<Result<(), AppError>>,
+}
+
+impl Pipeline {
+    pub fn spawn(
+        stream: FrameStream,
+        size: (u16, u16),
+        expected_frames: usize,
+        cache_path: &Path,
+        cache_header: &CacheHeader,
+        stop_signal: Arc<AtomicBool>,
+    ) -> Result<Self, AppError> {
+        let buffer = Arc::new(FrameBuffer::new(
+            expected_frames,
+            frames_in(BUFFER_AHEAD, cache_header.frame_rate),
+            frames_in(BUFFER_BEHIND, cache_header.frame_rate),
+        ));
+        let settings = cache_header.render.clone();
+        let mut cache_writer = CacheWriter::create(cache_path, cache_header)?;
+        let (tx, rx) = mpsc::channel();
+
+        let writer_buffer = Arc::clone(&buffer);
+        let writer = spawn_named("acsv-writer", move || {
+            let mut written = 0;
+            for message in rx {
+                let result = match message {
+                    CacheMessage::Frame(frame) => {
+                        written += 1;
+                        let result = cache_writer.push(&frame);
+                        // Frames the buffer drops are read back from the file
+                        if result.is_ok() && written % FRAME_BATCH_SIZE == 0 {
+                            writer_buffer.sync_history(&mut cache_writer)
+                        } else {
+                            result
+                        }
+                    }
+                    CacheMessage::Finish => {
+                        let result = writer_buffer.sync_history(&mut cache_writer);
+                        if result.is_ok() {
+                            return cache_writer.finish();
+                        }
+                        result
+                    }
+                };
+                if let Err(e) = result {
+                    cache_writer.abort();
+                    return Err(e);
+                }
+            }
+            // The converter went away without finishing, so the cache would be partial
+            cache_writer.abort();
+            Ok(())
+        })?;
+
+        let converter_buffer = Arc::clone(&buffer);
+        let converter = spawn_named("frame-converter", move || {
+            let result = convert_stream(
+                stream,
+                size,
+                &settings,
+                &converter_buffer,
+                &tx,
+                &stop_signal,
+            );
+            match &result {
+                Ok(()) => converter_buffer.complete(),
+                Err(AppError::Interrupted) => {
+                    log::debug!("Frame conversion stopped before the end of the video");
+                    converter_buffer.fail();
+                }
+                Err(e) => {
+                    log::error!("Frame conversion failed: {}", e);
+                    converter_buffer.fail();
+                }
+            }
+            result
+        })?;
+
+        Ok(Pipeline {
+            buffer,
+            converter,
+            writer,
+        })
+    }
+
+    pub fn buffer(&self) -> Arc<FrameBuffer> {
+        Arc::clone(&self.buffer)
+    }
+
+    /// Waits for the converter and cache writer to exit. A conversion that was
+    /// stopped because playback ended early is not treated as an error.
+    pub fn join(self) -> Result<(), AppError> {
+        let converted = self
+            .converter
+            .join()
+            .map_err(|_| AppError::FrameProcessing)?;
+        let written = self
+            .writer
+            .join()
+            .map_err(|_| AppError::CacheWrite("Cache writer thread panicked".to_string()))?;
+
+        match converted {
+            Ok(()) | Err(AppError::Interrupted) => written,
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn convert_stream(
+    mut stream: FrameStream,
+    size: (u16, u16),
+    settings: &RenderSettings,
+    buffer: &FrameBuffer,
+    cache_tx: &Sender<CacheMessage>,
+    stop_signal: &AtomicBool,
+) -> Result<(), AppError> {
+    loop {
+        if stop_signal.load(Ordering::Relaxed) {
+            return Err(AppError::Interrupted);
+        }
+
+        // Only a bounded batch of raw frames is held in memory at a time
+        let batch = stream
+            .by_ref()
+            .take(FRAME_BATCH_SIZE)
+            .collect::<Result<Vec<_>, _>>()?;
+        if batch.is_empty() {
+            break;
+        }
+
+        let frames: Vec<Arc<RleFrame>> = ascii::convert_frames_parallel(batch, size, settings)
+            .into_iter()
+            .map(Arc::new)
+            .collect();
+        for frame in &frames {
+            let _ = cache_tx.send(CacheMessage::Frame(Arc::clone(frame)));
+        }
+        buffer.push(frames, stop_signal)?;
+    }
+
+    stream.finish()?;
+    let _ = cache_tx.send(CacheMessage::Finish);
+    log::debug!("Converted {} frames from ffmpeg stream", buffer.len());
+    Ok(())
+}
+
+/// Frames that cover `duration` at `frame_rate`, and never less than a batch
+/// so the converter can always make progress.
+fn frames_in(duration: Duration, frame_rate: f32) -> usize {
+    ((duration.as_secs_f64() * frame_rate as f64).ceil() as usize).max(FRAME_BATCH_SIZE)
+}
+
+fn spawn_named<F>(name: &str, f: F) -> Result<JoinHandle<Result<(), AppError>>, AppError>
+where
+    F: FnOnce() -> Result<(), AppError> + Send + 'static,
+{
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(f)
+        .map_err(|e| AppError::Io {
+            source: e,
+            context: Some(format!("Failed to spawn {} thread", name)),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adjust::Adjustments;
+    use crate::ascii::{RenderMode, RleRun};
+    use crate::dither::Dither;
+    use crate::quantize::Quantization;
+
+    fn frame(i: usize) -> Arc<RleFrame> {
+        Arc::new(RleFrame {
+            width: 2,
+            runs: vec![RleRun {
+                ascii_idx: (i % 3) as u8,
+                color: [i as u8; 3],
+                bg: None,
+                count: 2,
+            }],
+            pts: Duration::from_millis(40 * i as u64),
+        })
+    }
+
+    #[test]
+    fn push_waits_for_the_playhead() {
+        let buffer = Arc::new(FrameBuffer::new(100, 8, 100));
+        buffer
+            .push((0..8).map(frame).collect(), &AtomicBool::new(false))
+            .unwrap();
+
+        let pusher = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || buffer.push(vec![frame(8)], &AtomicBool::new(false)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(buffer.len(), 8);
+
+        buffer.set_playhead(1);
+        pusher.join().unwrap().unwrap();
+        assert_eq!(buffer.len(), 9);
+    }
+
+    #[test]
+    fn push_gives_up_when_stopped() {
+        let buffer = FrameBuffer::new(100, 8, 100);
+        let stop = AtomicBool::new(false);
+        buffer.push((0..8).map(frame).collect(), &stop).unwrap();
+        stop.store(true, Ordering::Relaxed);
+        let result = buffer.push(vec![frame(8)], &stop);
+        assert!(matches!(result, Err(AppError::Interrupted)));
+    }
+
+    #[test]
+    fn dropped_frames_are_read_back_from_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let header = CacheHeader {
+            grid: (2, 2),
+            frame_rate: 25.0,
+            keyframe_interval: 4,
+            render: RenderSettings {
+                mode: RenderMode::Ascii,
+                ramp: vec![' ', '.', '#'],
+                dither: Dither::None,
+                adjustments: Adjustments::default(),
+                monochrome: false,
+                quantization: Quantization::default(),
+            },
+        };
+        let mut writer = CacheWriter::create(&dir.path().join("frames.acsv"), &header).unwrap();
+        let buffer = FrameBuffer::new(20, 100, 4);
+        let frames: Vec<_> = (0..20).map(frame).collect();
+        for frame in &frames {
+            writer.push(frame).unwrap();
+        }
+        buffer
+            .push(frames.clone(), &AtomicBool::new(false))
+            .unwrap();
+
+        buffer.set_playhead(16);
+        assert_eq!(buffer.len(), 20);
+        assert_eq!(buffer.first_available(), 12);
+        assert!(buffer.get(3).unwrap().is_none());
+
+        buffer.sync_history(&mut writer).unwrap();
+        assert_eq!(buffer.first_available(), 0);
+        for (idx, frame) in frames.iter().enumerate() {
+            let read = buffer.get(idx).unwrap().unwrap();
+            assert_eq!(read.cells(), frame.cells(), "cells of frame {}", idx);
+            assert_eq!(buffer.pts(idx), Some(frame.pts));
+            assert_eq!(buffer.frames_until(frame.pts), idx + 1);
+        }
+        writer.finish().unwrap();
+        assert_eq!(buffer.get(3).unwrap().unwrap().cells(), frames[3].cells());
+    }
+}
@@ -1,9 +1,14 @@
-use crate::ascii::RleFrame;
-use crate::color::rgb_to_ansi256;
-use crate::config::ASCII_CHARS;
+use crate::ascii::Cell;
+use crate::clock::{AudioClock, PlaybackClock};
+use crate::color::{ColorMode, PaletteMapper};
+use crate::config::{PAUSED_POLL_INTERVAL, PREBUFFER_DURATION, SEEK_STEP, SPEED_STEPS};
+use crate::dither::Dither;
 use crate::error::AppError;
 use crate::metrics::MetricsMonitor;
-use crate::terminal::TerminalManager;
+use crate::palette::Palette;
+use crate::pipeline::FrameSource;
+use crate::terminal::{InputAction, TerminalManager};
+use indicatif::{ProgressBar, ProgressStyle};
 use rodio::{Decoder, OutputStream, PlayError, Sink, Source};
 use std::collections::VecDeque;
 use std::fs::File;
@@ -17,101 +22,211 @@ use std::sync::{
 use std::thread;
 use std::time::{Duration, Instant};
 
-fn reconstruct_frame_string(frame: &RleFrame, compatibility_mode: bool) -> String {
-    if frame.width == 0 || frame.runs.is_empty() {
-        return String::new();
-    }
-    let total_chars: usize = frame.runs.iter().map(|r| r.count as usize).sum();
-    let approx_height = (total_chars as f32 / frame.width as f32).ceil() as usize;
-    let estimated_capacity = total_chars + frame.runs.len() * 8 + approx_height;
-    let mut buffer = String::with_capacity(estimated_capacity.max(frame.width as usize + 1));
-    let mut current_col: u32 = 0;
-    let mut current_color: Option<[u8; 3]> = None;
-    let mut current_ansi_color: Option<u8> = None;
-
-    for run in &frame.runs {
-        if compatibility_mode {
-            let ansi_color = rgb_to_ansi256(run.color[0], run.color[1], run.color[2]);
-            if current_ansi_color != Some(ansi_color) {
-                if current_color.is_some() || current_ansi_color.is_some() {
-                    buffer.push_str("\x1b[0m");
+/// Colors a run of cells is drawn with, as they will be sent to the terminal.
+/// The foreground is left out for blank cells, where it is never seen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Style {
+    TrueColor(Option<[u8; 3]>, Option<[u8; 3]>),
+    Ansi256(Option<u8>, Option<u8>),
+    Ansi16(Option<u8>, Option<u8>),
+    /// No escapes, the terminal's default colors.
+    Plain,
+}
+
+impl Style {
+    fn write(&self, w: &mut Vec<u8>) {
+        match *self {
+            Style::TrueColor(fg, bg) => {
+                if let Some(fg) = fg {
+                    write!(w, "\x1b[38;2;{};{};{}m", fg[0], fg[1], fg[2]).unwrap();
+                }
+                if let Some(bg) = bg {
+                    write!(w, "\x1b[48;2;{};{};{}m", bg[0], bg[1], bg[2]).unwrap();
                 }
-                let mut w = Vec::with_capacity(12);
-                write!(w, "\x1b[38;5;{}m", ansi_color).unwrap();
-                buffer.push_str(unsafe { std::str::from_utf8_unchecked(&w) });
-                current_ansi_color = Some(ansi_color);
             }
-        } else {
-            if current_color != Some(run.color) {
-                if current_color.is_some() {
-                    buffer.push_str("\x1b[0m");
+            Style::Ansi256(fg, bg) => {
+                if let Some(fg) = fg {
+                    write!(w, "\x1b[38;5;{}m", fg).unwrap();
                 }
-                let mut w = Vec::with_capacity(20);
-                write!(
-                    w,
-                    "\x1b[38;2;{};{};{}m",
-                    run.color[0], run.color[1], run.color[2]
-                )
-                .unwrap();
+                if let Some(bg) = bg {
+                    write!(w, "\x1b[48;5;{}m", bg).unwrap();
+                }
+            }
+            Style::Ansi16(fg, bg) => {
+                let code = |idx: u8, base: u8| {
+                    if idx < 8 {
+                        base + idx
+                    } else {
+                        base + 60 + idx - 8
+                    }
+                };
+                if let Some(fg) = fg {
+                    write!(w, "\x1b[{}m", code(fg, 30)).unwrap();
+                }
+                if let Some(bg) = bg {
+                    write!(w, "\x1b[{}m", code(bg, 40)).unwrap();
+                }
+            }
+            Style::Plain => {}
+        }
+    }
+}
 
-                // I hope this is faster
-                buffer.push_str(unsafe { std::str::from_utf8_unchecked(&w) });
-                current_color = Some(run.color);
+/// A cell as it is drawn: its glyph and the colors sent for it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StyledCell {
+    glyph: u8,
+    style: Style,
+}
+
+/// Resolves the colors of a frame for the terminal. For the palette modes the
+/// mapping is dithered across the whole frame, so a cell can change color even
+/// when its source cell did not.
+fn style_cells(
+    cells: &[Cell],
+    width: usize,
+    glyphs: &[char],
+    colors: &PaletteMapper,
+    dither: Dither,
+) -> Vec<StyledCell> {
+    let color = colors.mode();
+    let (fg, bg) = match color {
+        ColorMode::TrueColor | ColorMode::None => (Vec::new(), Vec::new()),
+        ColorMode::Ansi256 | ColorMode::Ansi16 | ColorMode::Gray => {
+            let fg: Vec<[u8; 3]> = cells.iter().map(|c| c.color).collect();
+            let bg = if cells.iter().any(|c| c.bg.is_some()) {
+                let bg: Vec<[u8; 3]> = cells.iter().map(|c| c.bg.unwrap_or_default()).collect();
+                colors.map_dithered(&bg, width, dither)
+            } else {
+                Vec::new()
+            };
+            (colors.map_dithered(&fg, width, dither), bg)
+        }
+    };
+    // Runs of blanks share one style whatever their foreground color
+    let shows_fg = |c: &Cell| glyphs.get(c.ascii_idx as usize).is_some_and(|&g| g != ' ');
+
+    cells
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let style = match color {
+                ColorMode::TrueColor => Style::TrueColor(shows_fg(c).then_some(c.color), c.bg),
+                ColorMode::None => Style::Plain,
+                ColorMode::Ansi16 => {
+                    Style::Ansi16(shows_fg(c).then_some(fg[i]), c.bg.map(|_| bg[i]))
+                }
+                ColorMode::Ansi256 | ColorMode::Gray => {
+                    Style::Ansi256(shows_fg(c).then_some(fg[i]), c.bg.map(|_| bg[i]))
+                }
+            };
+            StyledCell {
+                glyph: c.ascii_idx,
+                style,
             }
+        })
+        .collect()
+}
+
+/// Builds the output that turns the `previous` cells on screen into `cells`.
+/// Only changed cells are written, each stretch preceded by a cursor move; with
+/// no previous frame of the same size the whole frame is repainted.
+fn reconstruct_frame_string(
+    previous: Option<&[StyledCell]>,
+    cells: &[StyledCell],
+    width: u32,
+    glyphs: &[char],
+) -> String {
+    if width == 0 || cells.is_empty() {
+        return String::new();
+    }
+    let width = width as usize;
+    let previous = previous.filter(|p| p.len() == cells.len());
+    let mut buffer = String::with_capacity(cells.len() * 2);
+    let mut cursor: Option<usize> = None;
+    let mut current_style: Option<Style> = None;
+
+    for (i, cell) in cells.iter().enumerate() {
+        if previous.is_some_and(|p| p[i] == *cell) {
+            continue;
+        }
+
+        if cursor != Some(i) {
+            let mut w = Vec::with_capacity(12);
+            write!(w, "\x1b[{};{}H", i / width + 1, i % width + 1).unwrap();
+            buffer.push_str(unsafe { std::str::from_utf8_unchecked(&w) });
         }
 
-        let ch = ASCII_CHARS
-            .get(run.ascii_idx as usize)
-            .copied()
-            .unwrap_or(' ');
-
-        for _ in 0..run.count {
-            buffer.push(ch);
-            current_col += 1;
-            if current_col >= frame.width as u32 {
-                buffer.push('\n');
-                current_col = 0;
+        let style = cell.style;
+        if current_style != Some(style) {
+            let mut w = Vec::with_capacity(40);
+            // Reset first so a background from the previous run never leaks
+            // into a cell without one
+            if current_style.is_some_and(|s| s != Style::Plain) {
+                w.extend_from_slice(b"\x1b[0m");
             }
+            style.write(&mut w);
+
+            // I hope this is faster
+            buffer.push_str(unsafe { std::str::from_utf8_unchecked(&w) });
+            current_style = Some(style);
         }
-    }
 
-    if current_color.is_some() || current_ansi_color.is_some() {
-        buffer.push_str("\x1b[0m");
+        let ch = glyphs.get(cell.glyph as usize).copied().unwrap_or(' ');
+        buffer.push(ch);
+
+        // The cursor does not advance past the last column, so the next row
+        // always needs an explicit move
+        cursor = if (i + 1) % width == 0 {
+            None
+        } else {
+            Some(i + 1)
+        };
     }
 
-    if buffer.ends_with('\n') {
-        buffer.pop();
+    if current_style.is_some_and(|s| s != Style::Plain) {
+        buffer.push_str("\x1b[0m");
     }
     buffer
 }
 
+/// User-selected playback behaviour.
+#[derive(Debug, Clone)]
+pub struct PlaybackOptions {
+    pub color: ColorMode,
+    pub loop_video: bool,
+    pub speed: f32,
+    /// Milliseconds the video is delayed relative to the audio.
+    pub av_offset_ms: i64,
+    /// Characters the frames' glyph indices refer to.
+    pub glyphs: Vec<char>,
+    pub dither: Dither,
+    pub palette: Palette,
+}
+
 pub struct Player {
-    rle_frames: Vec<RleFrame>,
+    frames: FrameSource,
     audio_path: PathBuf,
+    audio_clock: AudioClock,
     sync_frame_delay: Duration,
     total_audio_duration: Duration,
     terminal_manager: TerminalManager,
     metrics_monitor: MetricsMonitor,
     pub stop_signal: Arc<AtomicBool>,
-    compatibility_mode: bool,
-    loop_video: bool,
+    colors: PaletteMapper,
+    options: PlaybackOptions,
 }
 
 impl Player {
     pub fn new(
-        rle_frames: Vec<RleFrame>,
+        frames: FrameSource,
         audio_path: PathBuf,
-        original_frame_rate: f32,
+        frame_rate: f32,
         terminal_manager: TerminalManager,
         metrics_monitor: MetricsMonitor,
-        compatibility_mode: bool,
-        loop_video: bool,
+        options: PlaybackOptions,
     ) -> Result<Self, AppError> {
-        if rle_frames.is_empty() {
-            return Err(AppError::FrameProcessing);
-        }
-
-        let num_frames = rle_frames.len();
+        let num_frames = frames.expected_len();
         let audio_duration = if audio_path.exists() {
             get_audio_duration(&audio_path)
                 .map_err(|e| {
@@ -133,8 +248,8 @@ impl Player {
             None
         };
 
-        let (sync_frame_delay, total_audio_duration) = if original_frame_rate > 0.0 {
-            let d = Duration::from_secs_f32(1.0 / original_frame_rate);
+        let (sync_frame_delay, total_audio_duration) = if frame_rate > 0.0 {
+            let d = Duration::from_secs_f32(1.0 / frame_rate);
             (d, audio_duration.unwrap_or(d * num_frames as u32))
         } else if let Some(dur) = audio_duration {
             if !dur.is_zero() {
@@ -149,21 +264,27 @@ impl Player {
         };
 
         Ok(Self {
-            rle_frames,
+            frames,
             audio_path,
+            audio_clock: AudioClock::new(),
             sync_frame_delay,
             total_audio_duration,
             terminal_manager,
             metrics_monitor,
             stop_signal: Arc::new(AtomicBool::new(false)),
-            compatibility_mode,
-            loop_video,
+            colors: PaletteMapper::new(options.color, &options.palette),
+            options,
         })
     }
 
     pub fn play(&mut self) -> Result<(), AppError> {
-        if self.rle_frames.is_empty() {
-            return Ok(());
+        self.prebuffer();
+        if self.frames.is_empty() {
+            return if self.frames.is_complete() {
+                Err(AppError::FrameProcessing)
+            } else {
+                Ok(())
+            };
         }
 
         let (_stream, handle) =
@@ -175,111 +296,309 @@ impl Player {
             source: PlayError::NoDevice,
             context: Some(format!("Sink error: {}", e)),
         })?;
-        if let Ok(file) = File::open(&self.audio_path) {
-            if let Ok(src) = Decoder::new(BufReader::new(file)) {
-                sink.append(src);
-                sink.pause();
-            }
-        }
-        thread::sleep(Duration::from_millis(2000));
+        // Held until the clock starts, so audio and video begin together
+        sink.pause();
+        sink.set_speed(self.options.speed);
+        self.append_audio(&sink);
         self.terminal_manager.setup()?;
         self.terminal_manager.clear()?;
         self.metrics_monitor.start();
 
-        sink.play();
-
         let start = Instant::now();
-        let mut idx = 0;
+        let mut clock = PlaybackClock::start(
+            self.options.speed as f64,
+            self.audio_clock.clone(),
+            self.options.av_offset_ms,
+        );
+        sink.play();
         let mut times = VecDeque::with_capacity(128);
+        let mut output_sizes = VecDeque::with_capacity(128);
+        let (mut total_bytes, mut total_frames) = (0usize, 0usize);
+        let mut displayed: Option<Vec<StyledCell>> = None;
+        let mut drawn_idx: Option<usize> = None;
 
-        while !self.stop_signal.load(Ordering::Relaxed) {
-            if self.loop_video {
-                idx = idx % self.rle_frames.len();
-            } else {
-                if idx >= self.rle_frames.len() {
-                    break;
+        'playback: while !self.stop_signal.load(Ordering::Relaxed) {
+            let mut redraw = false;
+            while let Some(action) = TerminalManager::poll_input()? {
+                if action == InputAction::Quit {
+                    break 'playback;
                 }
+                self.handle_input(action, &mut clock, &sink);
+                redraw = true;
             }
-            if TerminalManager::check_for_exit()? {
-                break;
-            }
-
-            let target = start + self.sync_frame_delay * (idx as u32);
-            let now = Instant::now();
+            clock.sync();
 
-            if now < target {
-                thread::sleep(target - now);
+            let mut idx = self.frame_index_at(clock.position());
+            self.frames.set_playhead(idx);
+            let available = self.frames.len();
+            if idx >= available {
+                if self.frames.is_complete() {
+                    if self.options.loop_video {
+                        self.seek(&mut clock, &sink, Duration::ZERO);
+                        idx = 0;
+                    } else if clock.is_paused() {
+                        // Paused past the end after a jump, show the last frame
+                        idx = available - 1;
+                    } else {
+                        break;
+                    }
+                } else if self.frames.is_failed() {
+                    break;
+                } else {
+                    // Playback caught up with the converter, hold the clock while more frames arrive
+                    let was_paused = clock.is_paused();
+                    clock.pause();
+                    sink.pause();
+                    self.frames.wait_for(idx + 1, Duration::from_millis(100));
+                    if !was_paused {
+                        clock.resume();
+                        sink.play();
+                    }
+                    continue;
+                }
             }
 
-            let frame_str =
-                reconstruct_frame_string(&self.rle_frames[idx], self.compatibility_mode);
-            let elapsed = Instant::now().saturating_duration_since(start);
-            let fps = times
-                .iter()
-                .filter(|&&t| elapsed - t < Duration::from_secs(1))
-                .count() as f32;
-            let status = format!(
-                "[Time: {} / {} | Frame: {} / {} | FPS: {:.1} | {}]",
-                format_duration(elapsed),
-                format_duration(self.total_audio_duration),
-                idx + 1,
-                self.rle_frames.len(),
-                fps,
-                self.metrics_monitor.get_metrics()
-            );
-            let (cols, _) = TerminalManager::get_size()?;
-            let bar = status.chars().count().min(cols as usize);
-            let padding_total = cols.saturating_sub(bar as u16);
-            let padding_left = padding_total / 2;
-            let padding_right = padding_total - padding_left;
-            let centered = format!(
-                "{}{}{}",
-                "=".repeat(padding_left as usize),
-                status,
-                "=".repeat(padding_right as usize)
-            );
+            if drawn_idx != Some(idx) || redraw {
+                let Some(frame) = self.frames.get(idx)? else {
+                    continue;
+                };
 
-            self.terminal_manager
-                .draw(&format!("{}{}", frame_str, centered))?;
+                let cells = style_cells(
+                    &frame.cells(),
+                    frame.width as usize,
+                    &self.options.glyphs,
+                    &self.colors,
+                    self.options.dither,
+                );
+                let frame_str = reconstruct_frame_string(
+                    displayed.as_deref(),
+                    &cells,
+                    frame.width,
+                    &self.options.glyphs,
+                );
+                let frame_height = cells.len().checked_div(frame.width as usize).unwrap_or(0);
+                displayed = Some(cells);
+                if let Some(prev) = drawn_idx
+                    && idx > prev + 1
+                    && !redraw
+                {
+                    log::debug!("Lag detected. Skipped {} frame(s).", idx - prev - 1);
+                }
+                drawn_idx = Some(idx);
 
-            times.push_back(Instant::now().saturating_duration_since(start));
-            if times.len() > 128 {
-                times.pop_front();
-            }
+                let elapsed = Instant::now().saturating_duration_since(start);
+                let fps = times
+                    .iter()
+                    .filter(|&&t| elapsed - t < Duration::from_secs(1))
+                    .count() as f32;
+                output_sizes.push_back(frame_str.len());
+                if output_sizes.len() > 128 {
+                    output_sizes.pop_front();
+                }
+                total_bytes += frame_str.len();
+                total_frames += 1;
+                let bytes_per_frame = output_sizes.iter().sum::<usize>() / output_sizes.len();
 
-            let now = Instant::now();
+                let status = format!(
+                    "[{}Time: {} / {} | Frame: {} / {} | Speed: {:.2}x | FPS: {:.1} | Out: {}/f | {}]",
+                    if clock.is_paused() { "PAUSED | " } else { "" },
+                    format_duration(clock.position()),
+                    format_duration(self.total_audio_duration),
+                    idx + 1,
+                    self.frames.expected_len(),
+                    clock.rate(),
+                    fps,
+                    format_bytes(bytes_per_frame),
+                    self.metrics_monitor.get_metrics()
+                );
+                let (cols, _) = TerminalManager::get_size()?;
+                let bar = status.chars().count().min(cols as usize);
+                let padding_total = cols.saturating_sub(bar as u16);
+                let padding_left = padding_total / 2;
+                let padding_right = padding_total - padding_left;
+                let centered = format!(
+                    "{}{}{}",
+                    "=".repeat(padding_left as usize),
+                    status,
+                    "=".repeat(padding_right as usize)
+                );
 
-            if now > target {
-                let lag = now.saturating_duration_since(target);
-                let skip = if self.sync_frame_delay.as_secs_f64() > 0.0 {
-                    lag.as_secs_f64() / self.sync_frame_delay.as_secs_f64()
-                } else {
-                    0.0
-                };
+                self.terminal_manager
+                    .draw(&frame_str, frame_height as u16, &centered)?;
 
-                let skip = skip.floor() as usize;
-
-                if skip > 0 {
-                    log::debug!(
-                        "Lag detected: {:?}. Skipping {} frame(s). (Current: {}, Next target: {})",
-                        lag,
-                        skip,
-                        idx + 1,
-                        idx + (skip + 1) + 1
-                    );
-                    idx += skip + 1;
-                } else {
-                    idx += 1;
+                times.push_back(Instant::now().saturating_duration_since(start));
+                if times.len() > 128 {
+                    times.pop_front();
                 }
-            } else {
-                idx += 1;
             }
+
+            let wait = clock
+                .time_until(self.frame_time(idx + 1))
+                .unwrap_or(PAUSED_POLL_INTERVAL);
+            thread::sleep(wait);
+        }
+        if let Some(average) = total_bytes.checked_div(total_frames) {
+            log::info!(
+                "Wrote {} frames, {} per frame on average.",
+                total_frames,
+                format_bytes(average)
+            );
         }
         self.stop_signal.store(true, Ordering::Relaxed);
         self.metrics_monitor.stop();
         sink.stop();
         Ok(())
     }
+
+    fn handle_input(&self, action: InputAction, clock: &mut PlaybackClock, sink: &Sink) {
+        let current = self.frame_index_at(clock.position());
+        let last = self.frames.expected_len().saturating_sub(1);
+        match action {
+            InputAction::Quit => {}
+            InputAction::TogglePause => {
+                if clock.is_paused() {
+                    clock.resume();
+                    sink.play();
+                } else {
+                    clock.pause();
+                    sink.pause();
+                }
+            }
+            InputAction::SeekBackward => {
+                self.seek(clock, sink, clock.position().saturating_sub(SEEK_STEP))
+            }
+            InputAction::SeekForward => self.seek(clock, sink, clock.position() + SEEK_STEP),
+            InputAction::StepBackward if clock.is_paused() => {
+                self.seek(clock, sink, self.frame_time(current.saturating_sub(1)))
+            }
+            InputAction::StepForward if clock.is_paused() => {
+                self.seek(clock, sink, self.frame_time((current + 1).min(last)))
+            }
+            InputAction::StepBackward | InputAction::StepForward => {}
+            InputAction::JumpToStart => self.seek(clock, sink, Duration::ZERO),
+            InputAction::JumpToEnd => self.seek(clock, sink, self.frame_time(last)),
+            InputAction::JumpToTenth(n) => {
+                self.seek(clock, sink, self.frame_time(last * n as usize / 10))
+            }
+            InputAction::SlowDown | InputAction::SpeedUp => {
+                let rate = clock.rate() as f32;
+                let next = if action == InputAction::SpeedUp {
+                    SPEED_STEPS
+                        .iter()
+                        .copied()
+                        .find(|&s| s > rate + f32::EPSILON)
+                } else {
+                    SPEED_STEPS
+                        .iter()
+                        .rev()
+                        .copied()
+                        .find(|&s| s < rate - f32::EPSILON)
+                };
+                if let Some(speed) = next {
+                    log::debug!("Playback speed changed to {}x", speed);
+                    clock.set_rate(speed as f64);
+                    sink.set_speed(speed);
+                }
+            }
+        }
+    }
+
+    /// Moves both the playback clock and the audio to `position`, clamped to
+    /// the frames that can be shown.
+    fn seek(&self, clock: &mut PlaybackClock, sink: &Sink, position: Duration) {
+        let last = self.frames.expected_len().saturating_sub(1);
+        let earliest = match self.frames.first_available() {
+            0 => Duration::ZERO,
+            first => self.frame_time(first),
+        };
+        let position = position.min(self.frame_time(last)).max(earliest);
+        log::debug!("Seeking to {:?}", position);
+        clock.seek(position);
+
+        if sink.empty() {
+            // The audio already played to the end, so queue it again
+            self.append_audio(sink);
+        }
+        if let Err(e) = sink.try_seek(clock.audio_position_for(position)) {
+            // The audio is no longer where the video is, so stop following it
+            log::debug!("Audio seek failed: {}", e);
+            self.audio_clock.detach();
+        }
+        if clock.is_paused() {
+            sink.pause();
+        } else {
+            sink.play();
+        }
+    }
+
+    fn append_audio(&self, sink: &Sink) {
+        if let Ok(file) = File::open(&self.audio_path)
+            && let Ok(src) = Decoder::new(BufReader::new(file))
+        {
+            sink.append(self.audio_clock.track(src));
+        }
+    }
+
+    /// The frame on screen at `position`: the last one whose timestamp has
+    /// passed. Frames not converted yet are assumed to follow the last known
+    /// one at the nominal frame rate.
+    fn frame_index_at(&self, position: Duration) -> usize {
+        let delay = self.sync_frame_delay.as_secs_f64();
+        match self.last_known_frame() {
+            Some((last, pts)) if position > pts => {
+                // Nudge forward so a position set from frame_time maps back to that frame
+                last + ((position - pts).as_secs_f64() / delay + 1e-6).floor() as usize
+            }
+            Some(_) => self.frames.frames_until(position).saturating_sub(1),
+            None => (position.as_secs_f64() / delay + 1e-6).floor() as usize,
+        }
+    }
+
+    fn frame_time(&self, idx: usize) -> Duration {
+        if let Some(pts) = self.frames.pts(idx) {
+            return pts;
+        }
+        match self.last_known_frame() {
+            Some((last, pts)) => pts + self.sync_frame_delay.mul_f64((idx - last) as f64),
+            None => self.sync_frame_delay.mul_f64(idx as f64),
+        }
+    }
+
+    /// Index and timestamp of the newest available frame.
+    fn last_known_frame(&self) -> Option<(usize, Duration)> {
+        let last = self.frames.len().checked_sub(1)?;
+        Some((last, self.frames.pts(last)?))
+    }
+
+    /// Waits until a few seconds of frames are buffered ahead of the playhead
+    /// (or conversion has ended) before playback starts.
+    fn prebuffer(&self) {
+        let target = ((PREBUFFER_DURATION.as_secs_f64() / self.sync_frame_delay.as_secs_f64())
+            .ceil() as usize)
+            .min(self.frames.expected_len())
+            .max(1);
+        if self.frames.len() >= target || self.frames.is_complete() {
+            return;
+        }
+
+        let pb = ProgressBar::new(target as u64);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "Buffering frames:   [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len}",
+                )
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        while !self.stop_signal.load(Ordering::Relaxed) {
+            let buffered = self.frames.wait_for(target, Duration::from_millis(100));
+            pb.set_position(buffered.min(target) as u64);
+            if buffered >= target || self.frames.is_complete() || self.frames.is_failed() {
+                break;
+            }
+        }
+        pb.finish_and_clear();
+    }
 }
 
 fn format_duration(d: Duration) -> String {
@@ -294,6 +613,14 @@ fn format_duration(d: Duration) -> String {
     }
 }
 
+fn format_bytes(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    }
+}
+
 fn get_audio_duration(path: &PathBuf) -> Result<Duration, AppError> {
     let file = File::open(path).map_err(|e| AppError::Io {
         source: e,
@@ -305,3 +632,44 @@ fn get_audio_duration(path: &PathBuf) -> Result<Duration, AppError> {
     })?;
     Ok(dec.total_duration().unwrap_or(Duration::ZERO))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A row of the background mode: blanks on a red and a blue background.
+    fn background_row() -> Vec<Cell> {
+        [[255, 0, 0], [0, 0, 255]]
+            .into_iter()
+            .map(|bg| Cell {
+                ascii_idx: 0,
+                color: [0; 3],
+                bg: Some(bg),
+            })
+            .collect()
+    }
+
+    fn render(cells: &[Cell], color: ColorMode) -> String {
+        let glyphs = [' '];
+        let colors = PaletteMapper::new(color, &Palette::default());
+        let styled = style_cells(cells, cells.len(), &glyphs, &colors, Dither::None);
+        reconstruct_frame_string(None, &styled, cells.len() as u32, &glyphs)
+    }
+
+    #[test]
+    fn background_cells_are_drawn_as_colored_blanks() {
+        assert_eq!(
+            render(&background_row(), ColorMode::TrueColor),
+            "\x1b[1;1H\x1b[48;2;255;0;0m \x1b[0m\x1b[48;2;0;0;255m \x1b[0m"
+        );
+        assert_eq!(
+            render(&background_row(), ColorMode::Ansi256),
+            "\x1b[1;1H\x1b[48;5;196m \x1b[0m\x1b[48;5;21m \x1b[0m"
+        );
+    }
+
+    #[test]
+    fn background_cells_are_blank_without_colors() {
+        assert_eq!(render(&background_row(), ColorMode::None), "\x1b[1;1H  ");
+    }
+}
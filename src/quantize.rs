@@ -0,0 +1,216 @@
+use crate::ascii::{Cell, RleFrame};
+use crate::config::MAX_QUANTIZE_COLORS;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How cell colors are reduced before run-length encoding, so neighbouring
+/// cells of nearly the same color end up in one run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Quantize {
+    /// Keep the exact colors.
+    #[default]
+    None,
+    /// Round every channel to a fixed grid.
+    Uniform,
+    /// Build a palette for each frame by median cut.
+    MedianCut,
+}
+
+impl Quantize {
+    pub fn name(self) -> &'static str {
+        match self {
+            Quantize::None => "none",
+            Quantize::Uniform => "uniform",
+            Quantize::MedianCut => "median-cut",
+        }
+    }
+}
+
+/// A quantization method together with how far, per channel, it may move a
+/// color.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quantization {
+    pub method: Quantize,
+    pub tolerance: u8,
+}
+
+impl Quantization {
+    pub fn is_none(&self) -> bool {
+        self.method == Quantize::None || self.tolerance == 0
+    }
+
+    /// Replaces the foreground and background colors of every cell and
+    /// re-encodes the runs.
+    pub fn apply(&self, frame: RleFrame) -> RleFrame {
+        if self.is_none() {
+            return frame;
+        }
+        let mut cells = frame.cells();
+        match self.method {
+            Quantize::None => {}
+            Quantize::Uniform => {
+                let step = 2 * self.tolerance as u16 + 1;
+                let round = |rgb: [u8; 3]| {
+                    rgb.map(|v| ((v as u16 / step * step + self.tolerance as u16).min(255)) as u8)
+                };
+                for cell in &mut cells {
+                    map_cell(cell, round);
+                }
+            }
+            Quantize::MedianCut => {
+                let palette = median_cut(&cells, self.tolerance);
+                for cell in &mut cells {
+                    map_cell(cell, |rgb| palette[&rgb]);
+                }
+            }
+        }
+        RleFrame {
+            pts: frame.pts,
+            ..RleFrame::from_cells(frame.width, &cells)
+        }
+    }
+}
+
+fn map_cell(cell: &mut Cell, f: impl Fn([u8; 3]) -> [u8; 3]) {
+    cell.color = f(cell.color);
+    cell.bg = cell.bg.map(&f);
+}
+
+/// Splits the colors of a frame at the median of their widest channel until
+/// every box spans at most twice the tolerance, or `MAX_QUANTIZE_COLORS` boxes
+/// exist. Each color maps to the middle of its box.
+fn median_cut(cells: &[Cell], tolerance: u8) -> HashMap<[u8; 3], [u8; 3]> {
+    let colors: Vec<[u8; 3]> = cells
+        .iter()
+        .flat_map(|c| std::iter::once(c.color).chain(c.bg))
+        .collect();
+    // Each box is kept with its widest channel and that channel's spread
+    let mut boxes = vec![with_widest_channel(colors)];
+    while boxes.len() < MAX_QUANTIZE_COLORS {
+        let Some((idx, _)) = boxes
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (_, _, range))| *range)
+        else {
+            break;
+        };
+        if boxes[idx].2 as u16 <= 2 * tolerance as u16 {
+            break;
+        }
+        let (mut colors, channel, _) = boxes.swap_remove(idx);
+        colors.sort_unstable_by_key(|c| c[channel]);
+        let upper = colors.split_off(colors.len() / 2);
+        boxes.push(with_widest_channel(colors));
+        boxes.push(with_widest_channel(upper));
+    }
+
+    let mut palette = HashMap::new();
+    for (colors, _, _) in &boxes {
+        let lo: [u8; 3] = std::array::from_fn(|c| colors.iter().map(|v| v[c]).min().unwrap_or(0));
+        let hi: [u8; 3] = std::array::from_fn(|c| colors.iter().map(|v| v[c]).max().unwrap_or(0));
+        let middle = std::array::from_fn(|c| ((lo[c] as u16 + hi[c] as u16) / 2) as u8);
+        for &color in colors {
+            palette.insert(color, middle);
+        }
+    }
+    palette
+}
+
+/// Pairs a box of colors with the channel that has the largest spread of
+/// values and the size of that spread.
+fn with_widest_channel(colors: Vec<[u8; 3]>) -> (Vec<[u8; 3]>, usize, u8) {
+    let (channel, range) = (0..3)
+        .map(|c| {
+            let (lo, hi) = colors.iter().fold((u8::MAX, u8::MIN), |(lo, hi), v| {
+                (lo.min(v[c]), hi.max(v[c]))
+            });
+            (c, hi.saturating_sub(lo))
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap();
+    (colors, channel, range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const METHODS: [Quantize; 2] = [Quantize::Uniform, Quantize::MedianCut];
+
+    fn frame(width: u32, colors: impl Iterator<Item = [u8; 3]>) -> RleFrame {
+        let cells: Vec<Cell> = colors
+            .map(|color| Cell {
+                ascii_idx: 0,
+                color,
+                bg: None,
+            })
+            .collect();
+        RleFrame::from_cells(width, &cells)
+    }
+
+    /// A mid gray with up to 3 levels of noise on every channel, which breaks
+    /// nearly every run.
+    fn noisy_frame() -> RleFrame {
+        frame(
+            64,
+            (0..64 * 16u32).map(|i| {
+                let noise = |salt: u32| ((i * 7919 + salt) % 7) as u8;
+                [124 + noise(0), 124 + noise(3), 124 + noise(5)]
+            }),
+        )
+    }
+
+    fn colors(frame: &RleFrame) -> HashSet<[u8; 3]> {
+        frame.runs.iter().map(|run| run.color).collect()
+    }
+
+    #[test]
+    fn zero_tolerance_keeps_the_frame() {
+        let original = noisy_frame();
+        for method in METHODS {
+            let quantization = Quantization {
+                method,
+                tolerance: 0,
+            };
+            assert!(quantization.is_none());
+            let quantized = quantization.apply(original.clone());
+            assert_eq!(quantized.cells(), original.cells(), "{}", method.name());
+        }
+    }
+
+    #[test]
+    fn noisy_frames_get_fewer_runs() {
+        let original = noisy_frame();
+        for method in METHODS {
+            let quantized = Quantization {
+                method,
+                tolerance: 8,
+            }
+            .apply(original.clone());
+            assert!(
+                quantized.runs.len() < original.runs.len() / 4,
+                "{}: {} runs of {}",
+                method.name(),
+                quantized.runs.len(),
+                original.runs.len()
+            );
+        }
+    }
+
+    #[test]
+    fn median_cut_palette_is_bounded() {
+        // Every cell has a color of its own
+        let original = frame(
+            64,
+            (0..64 * 64u32).map(|i| [(i % 64 * 4) as u8, (i / 64 * 4) as u8, 0]),
+        );
+        assert_eq!(colors(&original).len(), 64 * 64);
+        let quantized = Quantization {
+            method: Quantize::MedianCut,
+            tolerance: 1,
+        }
+        .apply(original);
+        assert_eq!(colors(&quantized).len(), MAX_QUANTIZE_COLORS);
+    }
+}
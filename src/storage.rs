@@ -1,120 +1,589 @@
-use crate::ascii::RleFrame;
-use crate::config::{ACSV_MAGIC, ACSV_VERSION, ZSTD_COMPRESSION_LEVEL};
+use crate::ascii::{Cell, DeltaFrame, RenderSettings, RleFrame, RleRun};
+use crate::config::{
+    ACSV_FOOTER_MAGIC, ACSV_LEGACY_VERSION, ACSV_MAGIC, ACSV_VERSION, FRAME_WINDOW_SIZE,
+    ZSTD_COMPRESSION_LEVEL,
+};
 use crate::error::AppError;
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
 use std::fs::{self, File};
-use std::io::{Read, Write};
-use std::path::Path;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-pub fn save_ascii_frames(file_path: &Path, rle_frames: &[RleFrame]) -> Result<(), AppError> {
-    let start_time = std::time::Instant::now();
-    log::info!(
-        "Saving {} frames to cache: {}",
-        rle_frames.len(),
-        file_path.display()
-    );
+// ACSV v3 layout:
+//
+//   magic | version | header len (u32) | header
+//   frame chunk 0 | frame chunk 1 | ...        (each an independent zstd frame)
+//   index                                      (offset, length, kind and timestamp of every chunk)
+//   index offset (u64) | index len (u32) | frame count (u32) | sha256 | footer magic
+//
+// The checksum covers the header and the index; every chunk carries its own
+// zstd content checksum, so a frame can be verified without reading the rest.
+//
+// Chunks are either keyframes holding a whole frame, or deltas holding only
+// the cells that changed since the previous frame. Reading frame N means
+// decoding from the nearest keyframe at or before N.
+const FOOTER_LEN: u64 = 8 + 4 + 4 + 32 + 4;
 
-    if let Some(parent) = file_path.parent() {
-        fs::create_dir_all(parent).map_err(|e| AppError::CreateDir(parent.to_path_buf(), e))?;
-    }
+/// Properties of the rendered frames stored at the start of an ACSV file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheHeader {
+    pub grid: (u16, u16),
+    pub frame_rate: f32,
+    pub keyframe_interval: u32,
+    pub render: RenderSettings,
+}
 
-    let pb_encode = ProgressBar::new_spinner();
-    pb_encode.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner} Serializing frame data...")
-            .unwrap(),
-    );
-    pb_encode.enable_steady_tick(Duration::from_millis(100));
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FrameIndexEntry {
+    offset: u64,
+    len: u32,
+    keyframe: bool,
+    /// Presentation time of the frame, which chunks do not store.
+    pts: Duration,
+}
 
-    let serialized_frames_data =
-        bincode::serde::encode_to_vec(&rle_frames, bincode::config::standard())
-            .map_err(|e| AppError::CacheWrite(format!("Frames serialization failed: {}", e)))?;
+#[derive(Serialize)]
+enum StoredFrameRef<'a> {
+    Key(&'a RleFrame),
+    Delta(&'a DeltaFrame),
+}
 
-    pb_encode.finish_and_clear();
-    log::debug!(
-        "Serialized frames size: {} bytes",
-        serialized_frames_data.len()
-    );
+/// Owned counterpart of `StoredFrameRef`, with the same serialized layout.
+#[derive(Deserialize)]
+enum StoredFrame {
+    Key(RleFrame),
+    Delta(DeltaFrame),
+}
 
-    let mut data_to_hash: Vec<u8> = Vec::new();
-    data_to_hash
-        .write_all(ACSV_MAGIC)
-        .map_err(|e| AppError::Io {
+/// Writes an ACSV cache incrementally while frames are still being
+/// produced. The file is written under a `.part` name and only renamed into
+/// place once `finish` has written the index.
+pub struct CacheWriter {
+    file_path: PathBuf,
+    part_path: PathBuf,
+    file: BufWriter<File>,
+    compressor: zstd::bulk::Compressor<'static>,
+    header: CacheHeader,
+    header_bytes: Vec<u8>,
+    keyframe_interval: u32,
+    previous: Option<(u32, Vec<Cell>)>,
+    frames_since_keyframe: u32,
+    offset: u64,
+    index: Vec<FrameIndexEntry>,
+}
+
+impl CacheWriter {
+    pub fn create(file_path: &Path, header: &CacheHeader) -> Result<Self, AppError> {
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| AppError::CreateDir(parent.to_path_buf(), e))?;
+        }
+
+        let header_bytes = bincode::serde::encode_to_vec(header, bincode::config::standard())
+            .map_err(|e| AppError::CacheWrite(format!("Header serialization failed: {}", e)))?;
+
+        let part_path = file_path.with_extension("acsv.part");
+        let file = File::create(&part_path).map_err(|e| AppError::Io {
             source: e,
-            context: Some("Writing ACSV_MAGIC".to_string()),
+            context: Some(part_path.display().to_string()),
         })?;
-    data_to_hash
-        .write_all(&[ACSV_VERSION])
-        .map_err(|e| AppError::Io {
+        let mut file = BufWriter::new(file);
+
+        let mut preamble = Vec::with_capacity(9 + header_bytes.len());
+        preamble.extend_from_slice(ACSV_MAGIC);
+        preamble.push(ACSV_VERSION);
+        preamble.extend_from_slice(&(header_bytes.len() as u32).to_le_bytes());
+        preamble.extend_from_slice(&header_bytes);
+        file.write_all(&preamble).map_err(|e| AppError::Io {
             source: e,
-            context: Some("Writing ACSV_VERSION".to_string()),
+            context: Some("Writing ACSV header".to_string()),
         })?;
-    data_to_hash
-        .write_all(&(rle_frames.len() as u32).to_le_bytes())
-        .map_err(|e| AppError::Io {
+
+        let mut compressor = zstd::bulk::Compressor::new(ZSTD_COMPRESSION_LEVEL).map_err(|e| {
+            AppError::Compression {
+                source: e,
+                context: Some(part_path.display().to_string()),
+            }
+        })?;
+        compressor
+            .set_parameter(zstd::zstd_safe::CParameter::ChecksumFlag(true))
+            .map_err(|e| AppError::Compression {
+                source: e,
+                context: Some("Enabling chunk checksums".to_string()),
+            })?;
+
+        log::debug!("Started incremental cache write to {}", file_path.display());
+        Ok(Self {
+            file_path: file_path.to_path_buf(),
+            part_path,
+            file,
+            compressor,
+            header: header.clone(),
+            header_bytes,
+            keyframe_interval: header.keyframe_interval.max(1),
+            previous: None,
+            frames_since_keyframe: 0,
+            offset: preamble.len() as u64,
+            index: Vec::new(),
+        })
+    }
+
+    pub fn push(&mut self, frame: &RleFrame) -> Result<(), AppError> {
+        let cells = frame.cells();
+        let delta = match &self.previous {
+            Some((width, previous))
+                if *width == frame.width
+                    && previous.len() == cells.len()
+                    && self.frames_since_keyframe + 1 < self.keyframe_interval =>
+            {
+                Some(DeltaFrame::between(previous, &cells))
+            }
+            _ => None,
+        };
+        // A delta that is no smaller than the frame itself (e.g. a scene cut)
+        // is stored as a keyframe instead
+        let delta = delta.filter(|d| d.run_count() < frame.runs.len());
+        let keyframe = delta.is_none();
+
+        let stored = match &delta {
+            Some(d) => StoredFrameRef::Delta(d),
+            None => StoredFrameRef::Key(frame),
+        };
+        let serialized = bincode::serde::encode_to_vec(&stored, bincode::config::standard())
+            .map_err(|e| AppError::CacheWrite(format!("Frame serialization failed: {}", e)))?;
+        let chunk = self
+            .compressor
+            .compress(&serialized)
+            .map_err(|e| AppError::Compression {
+                source: e,
+                context: Some(format!("Frame {}", self.index.len())),
+            })?;
+        self.file.write_all(&chunk).map_err(|e| AppError::Io {
             source: e,
-            context: Some("Writing frame count".to_string()),
+            context: Some("Writing frame chunk".to_string()),
         })?;
-    data_to_hash
-        .write_all(&serialized_frames_data)
-        .map_err(|e| AppError::Io {
+
+        self.index.push(FrameIndexEntry {
+            offset: self.offset,
+            len: chunk.len() as u32,
+            keyframe,
+            pts: frame.pts,
+        });
+        self.offset += chunk.len() as u64;
+        self.frames_since_keyframe = if keyframe {
+            0
+        } else {
+            self.frames_since_keyframe + 1
+        };
+        self.previous = Some((frame.width, cells));
+        Ok(())
+    }
+
+    /// Brings `reader` up to date with the frames written so far, first
+    /// opening it on the `.part` file if needed, so frames can be read back
+    /// before the cache is finished. The reader stays valid once the file is
+    /// renamed or discarded.
+    pub fn sync_reader(&mut self, reader: &mut Option<CacheReader>) -> Result<(), AppError> {
+        self.file.flush().map_err(|e| AppError::Io {
             source: e,
-            context: Some("Writing serialized frames".to_string()),
+            context: Some("Flushing frame chunks".to_string()),
         })?;
+        if reader.is_none() {
+            let file = File::open(&self.part_path).map_err(|e| AppError::Io {
+                source: e,
+                context: Some(self.part_path.display().to_string()),
+            })?;
+            *reader = Some(CacheReader {
+                file: Mutex::new(file),
+                header: self.header.clone(),
+                index: Vec::new(),
+                window: Mutex::new(VecDeque::with_capacity(FRAME_WINDOW_SIZE)),
+            });
+        }
+        if let Some(reader) = reader {
+            reader
+                .index
+                .extend_from_slice(&self.index[reader.index.len()..]);
+        }
+        Ok(())
+    }
 
-    let checksum = Sha256::digest(&data_to_hash);
-    log::debug!("Computed checksum: {:x?}", checksum.as_slice());
+    pub fn finish(mut self) -> Result<(), AppError> {
+        let start_time = std::time::Instant::now();
+        let result = self.write_index();
+        if result.is_err() {
+            let _ = fs::remove_file(&self.part_path);
+        }
+        result?;
 
-    // don't know why i need zstd for compressing, there has to be a more efficient way
-    let file = File::create(file_path).map_err(|e| AppError::Io {
-        source: e,
-        context: Some(file_path.display().to_string()),
-    })?;
+        fs::rename(&self.part_path, &self.file_path).map_err(|e| AppError::Io {
+            source: e,
+            context: Some(self.file_path.display().to_string()),
+        })?;
+
+        log::debug!(
+            "Saved {} frames to {} (took {:.2}s)",
+            self.index.len(),
+            self.file_path.display(),
+            start_time.elapsed().as_secs_f64()
+        );
+        Ok(())
+    }
+
+    /// Drops the partially written cache without producing a cache file.
+    pub fn abort(self) {
+        log::debug!(
+            "Discarding incomplete cache for {}",
+            self.file_path.display()
+        );
+        drop(self.file);
+        let _ = fs::remove_file(&self.part_path);
+    }
 
-    let mut encoder =
-        zstd::Encoder::new(file, ZSTD_COMPRESSION_LEVEL).map_err(|e| AppError::Compression {
+    fn write_index(&mut self) -> Result<(), AppError> {
+        let index_bytes =
+            bincode::serde::encode_to_vec(&self.index, bincode::config::standard())
+                .map_err(|e| AppError::CacheWrite(format!("Index serialization failed: {}", e)))?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(&self.header_bytes);
+        hasher.update(&index_bytes);
+        let checksum = hasher.finalize();
+        log::debug!("Computed checksum: {:x?}", checksum.as_slice());
+
+        let mut footer = Vec::with_capacity(FOOTER_LEN as usize);
+        footer.extend_from_slice(&self.offset.to_le_bytes());
+        footer.extend_from_slice(&(index_bytes.len() as u32).to_le_bytes());
+        footer.extend_from_slice(&(self.index.len() as u32).to_le_bytes());
+        footer.extend_from_slice(checksum.as_slice());
+        footer.extend_from_slice(ACSV_FOOTER_MAGIC);
+
+        self.file
+            .write_all(&index_bytes)
+            .and_then(|_| self.file.write_all(&footer))
+            .and_then(|_| self.file.flush())
+            .map_err(|e| AppError::Io {
+                source: e,
+                context: Some("Writing frame index".to_string()),
+            })
+    }
+}
+
+/// Random access to the frames of an ACSV cache. Only the index is read
+/// up front; frames are decompressed on demand and a small window of recently
+/// used frames is kept in memory.
+pub struct CacheReader {
+    file: Mutex<File>,
+    header: CacheHeader,
+    index: Vec<FrameIndexEntry>,
+    window: Mutex<VecDeque<(usize, Arc<RleFrame>)>>,
+}
+
+impl CacheReader {
+    pub fn open(file_path: &Path) -> Result<Self, AppError> {
+        let start_time = std::time::Instant::now();
+        let mut file = File::open(file_path).map_err(|e| AppError::Io {
             source: e,
             context: Some(file_path.display().to_string()),
         })?;
+        let file_len = file
+            .metadata()
+            .map_err(|e| AppError::Io {
+                source: e,
+                context: Some(file_path.display().to_string()),
+            })?
+            .len();
+        if file_len < 4 + 1 + 4 + FOOTER_LEN {
+            return Err(AppError::InvalidAcsv(format!(
+                "File too small ({} bytes) to contain header and index",
+                file_len
+            )));
+        }
 
-    let pb_write = ProgressBar::new_spinner();
-    pb_write.set_style(
-        ProgressStyle::default_spinner()
-            .template(&format!(
-                "{{spinner}} Compressing and writing frames data to {}...",
-                file_path.to_str().unwrap_or("cache file")
-            ))
-            .unwrap(),
-    );
-    pb_write.enable_steady_tick(Duration::from_millis(100));
+        let mut preamble = [0u8; 9];
+        read_exact_at(&mut file, 0, &mut preamble)?;
+        if &preamble[..4] != ACSV_MAGIC {
+            return Err(AppError::InvalidAcsv("Incorrect magic header".to_string()));
+        }
+        let version = preamble[4];
+        if version != ACSV_VERSION {
+            return Err(AppError::UnsupportedAcsvVersion(version));
+        }
+        let header_len = u32::from_le_bytes(preamble[5..9].try_into().unwrap()) as u64;
+        if 9 + header_len + FOOTER_LEN > file_len {
+            return Err(AppError::InvalidAcsv(
+                "Header length out of range".to_string(),
+            ));
+        }
+        let mut header_bytes = vec![0u8; header_len as usize];
+        read_exact_at(&mut file, 9, &mut header_bytes)?;
 
-    encoder.write_all(&data_to_hash).map_err(|e| AppError::Io {
-        source: e,
-        context: Some("Writing data to encoder".to_string()),
-    })?;
-    encoder
-        .write_all(checksum.as_slice())
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        read_exact_at(&mut file, file_len - FOOTER_LEN, &mut footer)?;
+        if &footer[48..52] != ACSV_FOOTER_MAGIC {
+            return Err(AppError::InvalidAcsv(
+                "Missing index footer, cache was not finished".to_string(),
+            ));
+        }
+        let index_offset = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+        let index_len = u32::from_le_bytes(footer[8..12].try_into().unwrap()) as u64;
+        let frame_count = u32::from_le_bytes(footer[12..16].try_into().unwrap());
+        let stored_checksum = &footer[16..48];
+        if index_offset + index_len + FOOTER_LEN != file_len {
+            return Err(AppError::InvalidAcsv(
+                "Index location out of range".to_string(),
+            ));
+        }
+        let mut index_bytes = vec![0u8; index_len as usize];
+        read_exact_at(&mut file, index_offset, &mut index_bytes)?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(&header_bytes);
+        hasher.update(&index_bytes);
+        let computed_checksum = hasher.finalize();
+        if stored_checksum != computed_checksum.as_slice() {
+            log::error!(
+                "Checksum mismatch! Stored: {:x?}, Computed: {:x?}",
+                stored_checksum,
+                computed_checksum.as_slice()
+            );
+            return Err(AppError::AcsvIntegrity);
+        }
+        log::debug!("Checksum verified successfully");
+
+        let (header, _): (CacheHeader, _) =
+            bincode::serde::decode_from_slice(&header_bytes, bincode::config::standard()).map_err(
+                |e| AppError::CacheRead(format!("Header deserialization failed: {}", e)),
+            )?;
+        let (index, _): (Vec<FrameIndexEntry>, _) =
+            bincode::serde::decode_from_slice(&index_bytes, bincode::config::standard())
+                .map_err(|e| AppError::CacheRead(format!("Index deserialization failed: {}", e)))?;
+
+        if index.len() != frame_count as usize {
+            return Err(AppError::InvalidAcsv(format!(
+                "Footer expected {} frames, but index lists {}",
+                frame_count,
+                index.len()
+            )));
+        }
+        if index
+            .iter()
+            .any(|e| e.offset < 9 + header_len || e.offset + e.len as u64 > index_offset)
+        {
+            return Err(AppError::InvalidAcsv(
+                "Frame index points outside the frame data".to_string(),
+            ));
+        }
+        if index.windows(2).any(|pair| pair[1].pts < pair[0].pts) {
+            return Err(AppError::InvalidAcsv(
+                "Frame timestamps go backwards".to_string(),
+            ));
+        }
+
+        log::info!(
+            "Opened cache {} with {} frames (took {:.2}s)",
+            file_path.display(),
+            index.len(),
+            start_time.elapsed().as_secs_f64()
+        );
+        Ok(Self {
+            file: Mutex::new(file),
+            header,
+            index,
+            window: Mutex::new(VecDeque::with_capacity(FRAME_WINDOW_SIZE)),
+        })
+    }
+
+    pub fn header(&self) -> &CacheHeader {
+        &self.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn pts(&self, idx: usize) -> Option<Duration> {
+        self.index.get(idx).map(|e| e.pts)
+    }
+
+    /// Number of frames shown at or before `position`.
+    pub fn frames_until(&self, position: Duration) -> usize {
+        self.index.partition_point(|e| e.pts <= position)
+    }
+
+    pub fn frame(&self, idx: usize) -> Result<Option<Arc<RleFrame>>, AppError> {
+        if idx >= self.index.len() {
+            return Ok(None);
+        }
+        if let Some(frame) = self.cached(idx) {
+            return Ok(Some(frame));
+        }
+
+        // Walk back to a frame decoding can start from: a keyframe, or a frame
+        // still in the window (the usual case during sequential playback)
+        let mut start = idx;
+        let base = loop {
+            if start < idx
+                && let Some(frame) = self.cached(start)
+            {
+                break Some(frame);
+            }
+            if self.index[start].keyframe {
+                break None;
+            }
+            if start == 0 {
+                return Err(AppError::InvalidAcsv(
+                    "First frame is not a keyframe".to_string(),
+                ));
+            }
+            start -= 1;
+        };
+        let (mut width, mut cells, first) = match base {
+            Some(frame) => (frame.width, frame.cells(), start + 1),
+            None => (0, Vec::new(), start),
+        };
+
+        for i in first..=idx {
+            match self.read_chunk(i)? {
+                StoredFrame::Key(frame) if i == idx => return Ok(Some(self.remember(idx, frame))),
+                StoredFrame::Key(frame) => {
+                    width = frame.width;
+                    cells = frame.cells();
+                }
+                StoredFrame::Delta(delta) => {
+                    if !delta.apply(&mut cells) {
+                        return Err(AppError::InvalidAcsv(format!(
+                            "Delta frame {} does not fit the frame before it",
+                            i
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(Some(
+            self.remember(idx, RleFrame::from_cells(width, &cells)),
+        ))
+    }
+
+    fn cached(&self, idx: usize) -> Option<Arc<RleFrame>> {
+        self.window
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(i, _)| *i == idx)
+            .map(|(_, frame)| Arc::clone(frame))
+    }
+
+    fn remember(&self, idx: usize, frame: RleFrame) -> Arc<RleFrame> {
+        let frame = Arc::new(RleFrame {
+            pts: self.index[idx].pts,
+            ..frame
+        });
+        let mut window = self.window.lock().unwrap();
+        if window.len() >= FRAME_WINDOW_SIZE {
+            window.pop_front();
+        }
+        window.push_back((idx, Arc::clone(&frame)));
+        frame
+    }
+
+    fn read_chunk(&self, idx: usize) -> Result<StoredFrame, AppError> {
+        let entry = &self.index[idx];
+        let mut chunk = vec![0u8; entry.len as usize];
+        read_exact_at(&mut self.file.lock().unwrap(), entry.offset, &mut chunk)?;
+        let serialized =
+            zstd::decode_all(chunk.as_slice()).map_err(|e| AppError::Decompression {
+                source: e,
+                context: Some(format!("Frame {}", idx)),
+            })?;
+        let (frame, _): (StoredFrame, _) =
+            bincode::serde::decode_from_slice(&serialized, bincode::config::standard()).map_err(
+                |e| AppError::CacheRead(format!("Frame {} deserialization failed: {}", idx, e)),
+            )?;
+        if let StoredFrame::Key(frame) = &frame {
+            frame
+                .validate()
+                .map_err(|e| AppError::InvalidAcsv(format!("Frame {}: {}", idx, e)))?;
+        }
+        Ok(frame)
+    }
+}
+
+fn read_exact_at(file: &mut File, offset: u64, buf: &mut [u8]) -> Result<(), AppError> {
+    file.seek(SeekFrom::Start(offset))
+        .and_then(|_| file.read_exact(buf))
+        .map_err(|e| AppError::Io {
+            source: e,
+            context: Some(format!("Reading {} bytes at offset {}", buf.len(), offset)),
+        })
+}
+
+/// Run layout of version 1 caches, which predate background colors.
+#[derive(Deserialize)]
+struct LegacyRleRun {
+    ascii_idx: u8,
+    color: [u8; 3],
+    count: u16,
+}
+
+#[derive(Deserialize)]
+struct LegacyRleFrame {
+    width: u16,
+    runs: Vec<LegacyRleRun>,
+}
+
+impl From<LegacyRleFrame> for RleFrame {
+    fn from(frame: LegacyRleFrame) -> Self {
+        RleFrame {
+            width: frame.width as u32,
+            runs: frame
+                .runs
+                .into_iter()
+                .map(|r| RleRun {
+                    ascii_idx: r.ascii_idx,
+                    color: r.color,
+                    bg: None,
+                    count: r.count,
+                })
+                .collect(),
+            pts: Duration::ZERO,
+        }
+    }
+}
+
+/// Frames loaded from an existing cache file.
+pub enum CachedFrames {
+    /// An indexed ACSV cache, read on demand.
+    Indexed(CacheReader),
+    /// A version 1 cache, which has to be decompressed in full.
+    Legacy(Vec<RleFrame>),
+}
+
+/// Opens a cache file of any supported version. Version 1 caches hold no
+/// timestamps, so their frames are spaced evenly at `frame_rate`.
+pub fn load_ascii_frames(file_path: &Path, frame_rate: f32) -> Result<CachedFrames, AppError> {
+    let mut magic = [0u8; 4];
+    File::open(file_path)
+        .and_then(|mut f| f.read_exact(&mut magic))
         .map_err(|e| AppError::Io {
             source: e,
-            context: Some("Writing checksum to encoder".to_string()),
+            context: Some(file_path.display().to_string()),
         })?;
-    encoder.finish().map_err(|e| AppError::Compression {
-        source: e,
-        context: Some(file_path.display().to_string()),
-    })?;
-    pb_write.finish_and_clear();
 
-    log::info!(
-        "Saved frames data to {} successfully (took {:.2}s)",
-        file_path.display(),
-        start_time.elapsed().as_secs_f64()
-    );
-    Ok(())
+    // Version 1 files are a single zstd stream, so the magic is only visible
+    // after decompression
+    if &magic == ACSV_MAGIC {
+        CacheReader::open(file_path).map(CachedFrames::Indexed)
+    } else {
+        load_legacy_frames(file_path, frame_rate).map(CachedFrames::Legacy)
+    }
 }
 
-pub fn load_ascii_frames(file_path: &Path) -> Result<Vec<RleFrame>, AppError> {
+fn load_legacy_frames(file_path: &Path, frame_rate: f32) -> Result<Vec<RleFrame>, AppError> {
     log::info!("Loading frames from {}...", file_path.display());
     let start_time = std::time::Instant::now();
 
@@ -173,10 +642,10 @@ pub fn load_ascii_frames(file_path: &Path) -> Result<Vec<RleFrame>, AppError> {
     offset += 4;
     let version = data_without_hash[offset];
     offset += 1;
-    if version != ACSV_VERSION {
+    if version != ACSV_LEGACY_VERSION {
         return Err(AppError::UnsupportedAcsvVersion(version));
     } else {
-        log::debug!("Cache version {} matches current version", version);
+        log::debug!("Cache version {} is a legacy single-stream cache", version);
     }
     let frame_count_bytes: [u8; 4] = data_without_hash[offset..offset + 4]
         .try_into()
@@ -199,7 +668,7 @@ pub fn load_ascii_frames(file_path: &Path) -> Result<Vec<RleFrame>, AppError> {
     );
     pb_decode.enable_steady_tick(Duration::from_millis(100));
 
-    let (rle_frames, _): (Vec<RleFrame>, _) =
+    let (rle_frames, _): (Vec<LegacyRleFrame>, _) =
         bincode::serde::decode_from_slice(serialized_frames_data, bincode::config::standard())
             .map_err(|e| AppError::CacheRead(format!("Frames deserialization failed: {}", e)))?;
 
@@ -214,29 +683,237 @@ pub fn load_ascii_frames(file_path: &Path) -> Result<Vec<RleFrame>, AppError> {
         );
     }
 
+    let frame_delay = Duration::from_secs_f64(1.0 / frame_rate as f64);
+    let frames: Vec<RleFrame> = rle_frames
+        .into_iter()
+        .enumerate()
+        .map(|(idx, frame)| RleFrame {
+            pts: frame_delay.mul_f64(idx as f64),
+            ..RleFrame::from(frame)
+        })
+        .collect();
+    for (idx, frame) in frames.iter().enumerate() {
+        frame
+            .validate()
+            .map_err(|e| AppError::InvalidAcsv(format!("Frame {}: {}", idx, e)))?;
+    }
+
     log::info!(
         "Loaded {} frames from {} successfully (took {:.2}s)",
-        rle_frames.len(),
+        frames.len(),
         file_path.display(),
         start_time.elapsed().as_secs_f64()
     );
-    Ok(rle_frames)
+    Ok(frames)
 }
 
-pub fn cleanup_frame_directory(frames_dir: &Path) -> Result<(), AppError> {
-    if frames_dir.exists() && frames_dir.is_dir() {
-        log::debug!(
-            "Cleaning up temporary frame directory: {}",
-            frames_dir.display()
-        );
-        fs::remove_dir_all(frames_dir)
-            .map_err(|e| AppError::CleanupFrames(frames_dir.to_path_buf(), e))?;
-        log::debug!("Successfully cleaned up {}", frames_dir.display());
-    } else {
-        log::debug!(
-            "Temporary frame directory not found or not a directory, skipping cleanup: {}",
-            frames_dir.display()
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adjust::Adjustments;
+    use crate::ascii::RenderMode;
+    use crate::dither::Dither;
+    use crate::quantize::Quantization;
+    use proptest::prelude::*;
+    use tempfile::TempDir;
+
+    const WIDTH: u32 = 16;
+    const HEIGHT: u32 = 6;
+    const KEYFRAME_INTERVAL: u32 = 10;
+    /// Enough frames that the reader's window has to drop some.
+    const FRAME_COUNT: usize = FRAME_WINDOW_SIZE * 2 + 7;
+
+    fn header() -> CacheHeader {
+        CacheHeader {
+            grid: (WIDTH as u16, HEIGHT as u16 + 1),
+            frame_rate: 30.0,
+            keyframe_interval: KEYFRAME_INTERVAL,
+            render: RenderSettings {
+                mode: RenderMode::Ascii,
+                ramp: vec![' ', '.', '#'],
+                dither: Dither::None,
+                adjustments: Adjustments::default(),
+                monochrome: false,
+                quantization: Quantization::default(),
+            },
+        }
+    }
+
+    /// A mostly static clip: each frame changes a few cells of the one before
+    /// it, with a scene cut every 37 frames. Timestamps are spaced unevenly,
+    /// as in a variable frame rate video.
+    fn clip() -> Vec<RleFrame> {
+        let mut cells = vec![
+            Cell {
+                ascii_idx: 0,
+                color: [0; 3],
+                bg: None,
+            };
+            (WIDTH * HEIGHT) as usize
+        ];
+        let mut seed = 0x2545_f491_u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let mut pts = Duration::ZERO;
+        (0..FRAME_COUNT)
+            .map(|i| {
+                let changes = if i % 37 == 36 { cells.len() } else { 3 };
+                for _ in 0..changes {
+                    let pos = next() as usize % cells.len();
+                    let value = next();
+                    cells[pos] = Cell {
+                        ascii_idx: (value % 3) as u8,
+                        color: [value as u8, (value >> 8) as u8, (value >> 16) as u8],
+                        bg: (value % 5 == 0).then_some([1, 2, 3]),
+                    };
+                }
+                pts += Duration::from_millis(20 + (next() % 30) as u64);
+                RleFrame {
+                    pts,
+                    ..RleFrame::from_cells(WIDTH, &cells)
+                }
+            })
+            .collect()
+    }
+
+    fn write_cache(frames: &[RleFrame]) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frames.acsv");
+        let mut writer = CacheWriter::create(&path, &header()).unwrap();
+        for frame in frames {
+            writer.push(frame).unwrap();
+        }
+        writer.finish().unwrap();
+        (dir, path)
+    }
+
+    fn assert_frame(reader: &CacheReader, frames: &[RleFrame], idx: usize) {
+        let frame = reader.frame(idx).unwrap().unwrap();
+        assert_eq!(frame.width, frames[idx].width, "width of frame {}", idx);
+        assert_eq!(frame.pts, frames[idx].pts, "timestamp of frame {}", idx);
+        assert_eq!(frame.cells(), frames[idx].cells(), "cells of frame {}", idx);
+    }
+
+    #[test]
+    fn frames_round_trip_in_order() {
+        let frames = clip();
+        let (_dir, path) = write_cache(&frames);
+        let reader = CacheReader::open(&path).unwrap();
+
+        assert_eq!(reader.len(), frames.len());
+        assert_eq!(reader.header().keyframe_interval, KEYFRAME_INTERVAL);
+        assert!(reader.index.iter().any(|e| !e.keyframe));
+        assert!(reader.index[0].keyframe);
+        // No more than `KEYFRAME_INTERVAL - 1` deltas follow a keyframe
+        for chain in reader.index.windows(KEYFRAME_INTERVAL as usize) {
+            assert!(chain.iter().any(|e| e.keyframe));
+        }
+        for idx in 0..frames.len() {
+            assert_frame(&reader, &frames, idx);
+        }
+        assert!(reader.frame(frames.len()).unwrap().is_none());
+    }
+
+    #[test]
+    fn frames_read_backwards() {
+        let frames = clip();
+        let (_dir, path) = write_cache(&frames);
+        let reader = CacheReader::open(&path).unwrap();
+        for idx in (0..frames.len()).rev() {
+            assert_frame(&reader, &frames, idx);
+        }
+    }
+
+    #[test]
+    fn frames_read_across_keyframe() {
+        let frames = clip();
+        let (_dir, path) = write_cache(&frames);
+        let reader = CacheReader::open(&path).unwrap();
+        let keyframe = (1..frames.len())
+            .find(|&i| reader.index[i].keyframe && !reader.index[i - 1].keyframe)
+            .unwrap();
+        for idx in [
+            keyframe + 1,
+            keyframe - 1,
+            keyframe,
+            keyframe - 2,
+            keyframe + 2,
+        ] {
+            assert_frame(&reader, &frames, idx);
+        }
+    }
+
+    #[test]
+    fn timestamps_locate_frames() {
+        let frames = clip();
+        let (_dir, path) = write_cache(&frames);
+        let reader = CacheReader::open(&path).unwrap();
+        for (idx, frame) in frames.iter().enumerate() {
+            assert_eq!(reader.pts(idx), Some(frame.pts));
+            assert_eq!(reader.frames_until(frame.pts), idx + 1);
+        }
+        assert_eq!(reader.frames_until(Duration::ZERO), 0);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn frames_read_in_any_order(
+            order in prop::collection::vec(0..FRAME_COUNT, 1..FRAME_COUNT * 2)
+        ) {
+            let frames = clip();
+            let (_dir, path) = write_cache(&frames);
+            let reader = CacheReader::open(&path).unwrap();
+            for idx in order {
+                assert_frame(&reader, &frames, idx);
+            }
+        }
+    }
+
+    /// Writes a cache, lets `damage` change its bytes and opens the result.
+    fn open_damaged(damage: impl FnOnce(&mut Vec<u8>)) -> Result<CacheReader, AppError> {
+        let (_dir, path) = write_cache(&clip());
+        let mut bytes = fs::read(&path).unwrap();
+        damage(&mut bytes);
+        fs::write(&path, &bytes).unwrap();
+        CacheReader::open(&path)
+    }
+
+    #[test]
+    fn truncated_footer_is_rejected() {
+        let result = open_damaged(|bytes| bytes.truncate(bytes.len() - 10));
+        assert!(matches!(result, Err(AppError::InvalidAcsv(_))));
+    }
+
+    #[test]
+    fn corrupted_checksum_is_rejected() {
+        let result = open_damaged(|bytes| {
+            let pos = bytes.len() - 4 - 32;
+            bytes[pos] ^= 0xff;
+        });
+        assert!(matches!(result, Err(AppError::AcsvIntegrity)));
+    }
+
+    #[test]
+    fn corrupted_index_is_rejected() {
+        let result = open_damaged(|bytes| {
+            let pos = bytes.len() - FOOTER_LEN as usize - 1;
+            bytes[pos] ^= 0xff;
+        });
+        assert!(matches!(result, Err(AppError::AcsvIntegrity)));
+    }
+
+    #[test]
+    fn corrupted_index_location_is_rejected() {
+        let result = open_damaged(|bytes| {
+            let pos = bytes.len() - FOOTER_LEN as usize;
+            bytes[pos] ^= 0x01;
+        });
+        assert!(matches!(result, Err(AppError::InvalidAcsv(_))));
     }
-    Ok(())
 }
@@ -1,8 +1,8 @@
 use crate::error::AppError;
 use crossterm::{
     cursor::{Hide, MoveTo, Show},
-    event::{Event, KeyCode, KeyEvent, KeyModifiers, poll, read},
-    execute,
+    event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, poll, read},
+    execute, queue,
     style::Print,
     terminal::{
         Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen, SetSize, disable_raw_mode,
@@ -15,10 +15,26 @@ use std::{
     time::Duration,
 };
 
+/// Playback controls bound to keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputAction {
+    Quit,
+    TogglePause,
+    SeekBackward,
+    SeekForward,
+    StepBackward,
+    StepForward,
+    JumpToStart,
+    JumpToEnd,
+    /// Jump to the given tenth of the video (1 = 10%, ..., 9 = 90%).
+    JumpToTenth(u8),
+    SlowDown,
+    SpeedUp,
+}
+
 pub struct TerminalManager {
     stdout: Stdout,
     original_size: Option<(u16, u16)>,
-    previous_frame: String,
 }
 
 impl TerminalManager {
@@ -26,7 +42,6 @@ impl TerminalManager {
         TerminalManager {
             stdout: stdout(),
             original_size: None,
-            previous_frame: String::new(),
         }
     }
 
@@ -87,71 +102,77 @@ impl TerminalManager {
             })
     }
 
-    pub fn check_for_exit() -> Result<bool, AppError> {
-        if poll(Duration::from_millis(1)).map_err(|e| {
+    /// Reads one pending key press, if any, and maps it to a playback action.
+    pub fn poll_input() -> Result<Option<InputAction>, AppError> {
+        if !poll(Duration::from_millis(1)).map_err(|e| {
             error!("Failed to poll terminal events: {}", e);
             AppError::Terminal {
                 source: e,
                 context: Some("poll terminal events".to_string()),
             }
         })? {
-            match read().map_err(|e| {
-                error!("Failed to read from terminal: {}", e);
-                AppError::Terminal {
-                    source: e,
-                    context: Some("read terminal input".to_string()),
-                }
-            })? {
-                Event::Key(KeyEvent {
-                    code: KeyCode::Esc, ..
-                }) => {
-                    debug!("Escape key pressed, exiting");
-                    return Ok(true);
-                }
-                Event::Key(KeyEvent {
-                    code: KeyCode::Char('c'),
-                    modifiers: KeyModifiers::CONTROL,
-                    ..
-                }) => {
-                    debug!("Ctrl+C pressed, exiting");
-                    return Ok(true);
-                }
-                _ => {}
-            }
+            return Ok(None);
         }
-        Ok(false)
-    }
 
-    pub fn draw(&mut self, content: &str) -> Result<(), AppError> {
-        let old_lines = self.previous_frame.lines().collect::<Vec<_>>();
-        let new_lines = content.lines().collect::<Vec<_>>();
-
-        for (y, new_line) in new_lines.iter().enumerate() {
-            if y >= old_lines.len() || old_lines[y] != *new_line {
-                execute!(self.stdout, MoveTo(0, y as u16), Print(new_line)).map_err(|e| {
-                    AppError::Io {
-                        source: e,
-                        context: Some("Failed to write to terminal".to_string()),
-                    }
-                })?;
+        let event = read().map_err(|e| {
+            error!("Failed to read from terminal: {}", e);
+            AppError::Terminal {
+                source: e,
+                context: Some("read terminal input".to_string()),
             }
+        })?;
+        let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind,
+            ..
+        }) = event
+        else {
+            return Ok(None);
+        };
+        if kind == KeyEventKind::Release {
+            return Ok(None);
         }
 
-        if new_lines.len() < old_lines.len() {
-            for y in new_lines.len()..old_lines.len() {
-                execute!(
-                    self.stdout,
-                    MoveTo(0, y as u16),
-                    Clear(ClearType::CurrentLine)
-                )
-                .map_err(|e| AppError::Io {
-                    source: e,
-                    context: Some("Failed to clear line".to_string()),
-                })?;
+        let action = match code {
+            KeyCode::Esc => {
+                debug!("Escape key pressed, exiting");
+                InputAction::Quit
             }
-        }
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                debug!("Ctrl+C pressed, exiting");
+                InputAction::Quit
+            }
+            KeyCode::Char(' ') => InputAction::TogglePause,
+            KeyCode::Left => InputAction::SeekBackward,
+            KeyCode::Right => InputAction::SeekForward,
+            KeyCode::Char(',') => InputAction::StepBackward,
+            KeyCode::Char('.') => InputAction::StepForward,
+            KeyCode::Home => InputAction::JumpToStart,
+            KeyCode::End => InputAction::JumpToEnd,
+            KeyCode::Char(c @ '1'..='9') => InputAction::JumpToTenth(c as u8 - b'0'),
+            KeyCode::Char('[') => InputAction::SlowDown,
+            KeyCode::Char(']') => InputAction::SpeedUp,
+            _ => return Ok(None),
+        };
+        Ok(Some(action))
+    }
 
-        self.previous_frame = content.to_string();
+    /// Writes a frame update followed by the status bar on `status_row`. The
+    /// frame output positions its own cursor, so only the cells it covers are
+    /// touched.
+    pub fn draw(&mut self, frame: &str, status_row: u16, status: &str) -> Result<(), AppError> {
+        queue!(
+            self.stdout,
+            Print(frame),
+            MoveTo(0, status_row),
+            Print(status),
+            Clear(ClearType::UntilNewLine)
+        )
+        .map_err(|e| AppError::Io {
+            source: e,
+            context: Some("Failed to write to terminal".to_string()),
+        })?;
 
         self.stdout.flush().map_err(|e| AppError::Io {
             source: e,
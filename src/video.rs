@@ -1,13 +1,20 @@
-use crate::{error::AppError, utils::get_file_stem};
-use indicatif::{ProgressBar, ProgressStyle};
+use crate::{
+    ascii::{RenderMode, fit_dimensions},
+    error::AppError,
+    utils::get_file_stem,
+};
+use image::RgbImage;
 use log::{debug, error, info};
+use serde::Deserialize;
 use std::{
     fs,
+    io::{BufRead, BufReader, ErrorKind, Read},
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Child, ChildStderr, ChildStdout, Command, Stdio},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
     time::Duration,
 };
-use tempfile::TempDir;
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -16,17 +23,105 @@ pub struct VideoInfo {
     pub frame_rate: f32,
     pub total_frames: u64,
     pub duration: Duration,
+    /// Size of the picture as displayed, after rotation and sample aspect
+    /// ratio are applied, rather than the coded size of the frames.
     pub width: u32,
     pub height: u32,
+    /// Clockwise rotation of the picture in degrees.
+    pub rotation: u32,
     pub base_name: String,
     pub data_dir: PathBuf,
-    pub frames_dir: TempDir,
     pub audio_path: PathBuf,
     pub ascii_cache_path: PathBuf,
+    /// The part of the video that is played; `duration` and `total_frames`
+    /// describe this part only.
+    pub range: TimeRange,
+}
+
+/// A frame rate to convert a video to while decoding it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Resample {
+    pub fps: f32,
+    /// Blend neighbouring frames into the new ones instead of dropping or
+    /// repeating frames.
+    pub blend: bool,
+}
+
+impl Resample {
+    /// Identifies the conversion in cache file names.
+    pub fn cache_key(&self) -> String {
+        let method = if self.blend { "blend" } else { "fps" };
+        format!("{}{}", method, self.fps)
+    }
+
+    /// Frames a segment of `duration` has after the conversion.
+    pub fn frame_count(&self, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * self.fps as f64).round() as u64
+    }
+}
+
+/// A segment of a video, as positions in the source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start: Duration,
+    /// Where the segment stops; `None` runs to the end of the video.
+    pub end: Option<Duration>,
+}
+
+impl TimeRange {
+    pub fn new(start: Duration, end: Option<Duration>) -> Result<Self, AppError> {
+        if let Some(end) = end
+            && end <= start
+        {
+            return Err(AppError::InvalidTimeRange(format!(
+                "end {:.3}s is not after start {:.3}s",
+                end.as_secs_f64(),
+                start.as_secs_f64()
+            )));
+        }
+        Ok(TimeRange { start, end })
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.start.is_zero() && self.end.is_none()
+    }
+
+    /// Identifies the segment in file names, in milliseconds. Empty for the
+    /// whole video, so its files keep their names.
+    pub fn cache_key(&self) -> String {
+        if self.is_full() {
+            return String::new();
+        }
+        match self.end {
+            Some(end) => format!("t{}-{}", self.start.as_millis(), end.as_millis()),
+            None => format!("t{}-end", self.start.as_millis()),
+        }
+    }
+
+    /// ffmpeg input options limiting decoding to the segment. Seeking on the
+    /// input is exact when decoding, and audio and video extracted with the
+    /// same options both start at the same position.
+    fn input_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.start.is_zero() {
+            args.push("-ss".to_string());
+            args.push(format!("{:.3}", self.start.as_secs_f64()));
+        }
+        if let Some(end) = self.end {
+            args.push("-to".to_string());
+            args.push(format!("{:.3}", end.as_secs_f64()));
+        }
+        args
+    }
 }
 
 impl VideoInfo {
-    pub fn analyze(video_path: &Path, terminal_size: (u16, u16)) -> Result<Self, AppError> {
+    pub fn analyze(
+        video_path: &Path,
+        terminal_size: (u16, u16),
+        range: TimeRange,
+        cache_key: &str,
+    ) -> Result<Self, AppError> {
         if !video_path.is_file() {
             return Err(AppError::VideoNotFound(video_path.to_path_buf()));
         }
@@ -34,19 +129,6 @@ impl VideoInfo {
         let base_name = get_file_stem(video_path);
         info!("Analyzing video: {}", video_path.display());
 
-        let frames_dir = tempfile::Builder::new()
-            .prefix(&format!("frames_{}", base_name))
-            .tempdir()
-            .map_err(|e| {
-                error!("Failed to create temp directory for frames: {}", e);
-                AppError::Io {
-                    source: e,
-                    context: Some("tempdir creation".to_string()),
-                }
-            })?;
-
-        debug!("Created temporary directory at: {:?}", frames_dir.path());
-
         let data_dir = PathBuf::from("data").join(&base_name);
         fs::create_dir_all(&data_dir).map_err(|e| {
             error!("Failed to create data directory: {}", e);
@@ -58,10 +140,22 @@ impl VideoInfo {
 
         debug!("Created data directory at: {}", data_dir.display());
 
-        let audio_path = data_dir.join("audio.wav");
+        // Clips get files of their own, so they never replace the files of
+        // the whole video or of another clip
+        let range_key = range.cache_key();
+        let audio_path = data_dir.join(if range_key.is_empty() {
+            "audio.wav".to_string()
+        } else {
+            format!("audio-{}.wav", range_key)
+        });
+        let suffix: String = [cache_key, range_key.as_str()]
+            .iter()
+            .filter(|key| !key.is_empty())
+            .map(|key| format!("-{}", key))
+            .collect();
         let ascii_cache_path = data_dir.join(format!(
-            "frames_{}-{}.acsv",
-            terminal_size.0, terminal_size.1
+            "frames_{}-{}{}.acsv",
+            terminal_size.0, terminal_size.1, suffix
         ));
 
         let output = Command::new("ffprobe")
@@ -71,9 +165,10 @@ impl VideoInfo {
                 "-select_streams",
                 "v:0",
                 "-show_entries",
-                "stream=width,height,r_frame_rate,nb_frames",
+                "stream=width,height,sample_aspect_ratio,r_frame_rate,avg_frame_rate,nb_frames,duration\
+                 :stream_tags=rotate:stream_side_data=rotation:format=duration",
                 "-of",
-                "csv=p=0:s=,",
+                "json",
                 video_path
                     .to_str()
                     .ok_or_else(|| AppError::VideoMetadata(video_path.to_path_buf()))?,
@@ -87,29 +182,16 @@ impl VideoInfo {
             ));
         }
 
-        let binding = String::from_utf8(output.stdout).map_err(|e| AppError::Utf8 {
-            source: e,
-            context: Some("ffprobe output".to_string()),
-        })?;
-        let parts: Vec<&str> = binding.trim().split(',').collect();
-        if parts.len() != 4 {
-            return Err(AppError::VideoMetadata(video_path.to_path_buf()));
-        }
-
-        let width: u32 = parts[0].parse().map_err(|e| AppError::ParseInt {
-            source: e,
-            context: Some("width parse".to_string()),
-        })?;
-        let height: u32 = parts[1].parse().map_err(|e| AppError::ParseInt {
-            source: e,
-            context: Some("height parse".to_string()),
-        })?;
-        let frame_rate = parse_fps(parts[2]);
-        let total_frames: u64 = parts[3].parse().map_err(|e| AppError::ParseInt {
-            source: e,
-            context: Some("total_frames parse".to_string()),
-        })?;
-        let duration = Duration::from_secs_f32(total_frames as f32 / frame_rate);
+        let probe: ProbeOutput = serde_json::from_slice(&output.stdout)
+            .map_err(|e| AppError::FFprobe(format!("Unreadable ffprobe output: {}", e)))?;
+        let StreamProperties {
+            frame_rate,
+            total_frames,
+            duration,
+            width,
+            height,
+            rotation,
+        } = StreamProperties::from_probe(probe, range, video_path)?;
 
         Ok(VideoInfo {
             video_path: video_path.to_path_buf(),
@@ -118,11 +200,12 @@ impl VideoInfo {
             duration,
             width,
             height,
+            rotation,
             base_name,
             data_dir,
-            frames_dir,
             audio_path,
             ascii_cache_path,
+            range,
         })
     }
 
@@ -164,8 +247,9 @@ impl VideoInfo {
 
         // If we get here, the video has an audio stream, so try to extract it
         let output = Command::new("ffmpeg")
+            .arg("-y")
+            .args(self.range.input_args())
             .args(&[
-                "-y",
                 "-i",
                 self.video_path.to_str().unwrap(),
                 "-vn",
@@ -203,94 +287,754 @@ impl VideoInfo {
         }
     }
 
-    pub fn extract_frames(&self) -> Result<Vec<PathBuf>, AppError> {
-        fs::create_dir_all(self.frames_dir.path()).map_err(|e| AppError::Io {
-            source: e,
-            context: Some(self.frames_dir.path().display().to_string()),
-        })?;
-        let pattern = self.frames_dir.path().join("frame_%06d.png");
-
-        let pb = ProgressBar::new(self.total_frames);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("Extracting frames:  [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
-                .unwrap()
-                .progress_chars("=> "),
+    /// Spawns ffmpeg decoding the video as raw RGB24 frames already scaled to
+    /// fit the terminal grid, streamed over its stdout. With `resample` the
+    /// frames are converted to its frame rate on the way.
+    pub fn stream_frames(
+        &self,
+        terminal_size: (u16, u16),
+        render_mode: RenderMode,
+        resample: Option<Resample>,
+    ) -> Result<FrameStream, AppError> {
+        let (width, height) = fit_dimensions(
+            self.width,
+            self.height,
+            terminal_size.0,
+            terminal_size.1,
+            render_mode,
         );
+        let flags = if width < self.width || height < self.height {
+            "bilinear"
+        } else {
+            "bicubic"
+        };
+        // Frames are passed through at their own timestamps, which showinfo
+        // reports on stderr, so variable frame rate videos keep their timing
+        let scale = format!("scale={}:{}:flags={}", width, height, flags);
+        let filter = match resample {
+            None => format!("{},showinfo", scale),
+            // Dropped frames are left out before they are scaled, while
+            // blending is cheaper on the scaled frames
+            Some(r) if r.blend => format!("{},framerate=fps={},showinfo", scale, r.fps),
+            Some(r) => format!("fps={},{},showinfo", r.fps, scale),
+        };
+        let frame_rate = resample.map_or(self.frame_rate, |r| r.fps);
+        debug!("Streaming frames from ffmpeg with filter: {}", filter);
 
         let mut child = Command::new("ffmpeg")
-            .args(&[
+            .args(["-hide_banner", "-nostats"])
+            .args(self.range.input_args())
+            .args([
                 "-i",
                 self.video_path.to_str().unwrap(),
                 "-vf",
-                &format!("fps={}", self.frame_rate),
+                &filter,
+                passthrough_option(),
+                "passthrough",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgb24",
                 "-loglevel",
-                "quiet",
-                pattern.to_str().unwrap(),
+                "level+info",
+                "-",
             ])
-            .stdout(Stdio::null())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
             .map_err(|e| AppError::FFmpeg(format!("Failed to start ffmpeg for frames: {}", e)))?;
 
-        while match child.try_wait() {
-            Ok(Some(status)) => {
-                log::debug!("FFmpeg frame extraction finished with status: {}", status);
-                false
-            }
-            Ok(None) => true,
-            Err(e) => {
-                pb.finish_and_clear();
-                return Err(AppError::FFmpeg(format!(
-                    "Error waiting for ffmpeg (frames): {}",
-                    e
-                )));
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| AppError::FFmpeg("Failed to capture ffmpeg stdout".to_string()))?;
+
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| AppError::FFmpeg("Failed to capture ffmpeg stderr".to_string()))?;
+        let (timestamps_tx, timestamps) = mpsc::channel();
+        let stderr_reader = thread::Builder::new()
+            .name("ffmpeg-stderr".to_string())
+            .spawn(move || read_frame_log(stderr, timestamps_tx))
+            .map_err(|e| AppError::Io {
+                source: e,
+                context: Some("Failed to spawn ffmpeg-stderr thread".to_string()),
+            })?;
+
+        Ok(FrameStream {
+            child,
+            reader: BufReader::new(stdout),
+            width,
+            height,
+            timestamps: FrameTimestamps::new(
+                timestamps,
+                Duration::from_secs_f64(1.0 / frame_rate as f64),
+            ),
+            stderr_reader: Some(stderr_reader),
+            finished: false,
+        })
+    }
+}
+
+/// Option that keeps frames at their own timestamps: `-fps_mode`, or its
+/// older name `-vsync` on ffmpeg builds before 5.1 that lack it.
+fn passthrough_option() -> &'static str {
+    let supported = Command::new("ffmpeg")
+        .args(["-hide_banner", "-h", "long"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains("-fps_mode"));
+    if supported { "-fps_mode" } else { "-vsync" }
+}
+
+/// Reads ffmpeg's log while frames are streamed. Frame timestamps from
+/// showinfo are sent as they appear, with the number of the frame they belong
+/// to; errors are collected and returned for `FrameStream::finish` to report.
+fn read_frame_log(stderr: ChildStderr, timestamps: Sender<(u64, Option<f64>)>) -> String {
+    let mut errors = String::new();
+    for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+        if let Some(frame) = parse_showinfo_frame(&line) {
+            let _ = timestamps.send(frame);
+        } else if line.contains("[error]") || line.contains("[fatal]") || line.contains("[panic]") {
+            errors.push_str(&line);
+            errors.push('\n');
+        }
+    }
+    errors
+}
+
+/// The frame number `n` and `pts_time` of a showinfo line describing a
+/// frame. The time is `None` when the frame has none (`NOPTS`) or it cannot be
+/// read, so the frame still gets an entry.
+fn parse_showinfo_frame(line: &str) -> Option<(u64, Option<f64>)> {
+    if !line.contains("Parsed_showinfo") {
+        return None;
+    }
+    let field = |name: &str| {
+        let (_, rest) = line.split_once(name)?;
+        rest.split_whitespace().next()
+    };
+    let n = field(" n:")?.parse().ok()?;
+    let pts = field(" pts_time:")?
+        .parse::<f64>()
+        .ok()
+        .filter(|pts| pts.is_finite());
+    Some((n, pts))
+}
+
+/// Presentation times of the frames read from a `FrameStream`, matched to
+/// them by frame number as the log lines arrive.
+struct FrameTimestamps {
+    logged: Receiver<(u64, Option<f64>)>,
+    /// A log entry for a frame that has not been read yet.
+    pending: Option<(u64, Option<f64>)>,
+    frames_read: u64,
+    /// Timestamp of the first frame, which playback starts from.
+    first_pts: Option<f64>,
+    last_pts: Option<Duration>,
+    /// Spacing assumed for frames that ffmpeg reported no timestamp for.
+    frame_delay: Duration,
+}
+
+impl FrameTimestamps {
+    fn new(logged: Receiver<(u64, Option<f64>)>, frame_delay: Duration) -> Self {
+        FrameTimestamps {
+            logged,
+            pending: None,
+            frames_read: 0,
+            first_pts: None,
+            last_pts: None,
+            frame_delay,
+        }
+    }
+
+    /// Presentation time of the next frame, counted from the first frame.
+    /// Timestamps never go backwards; a frame without one is placed a nominal
+    /// frame after the previous frame.
+    fn next(&mut self) -> Duration {
+        let n = self.frames_read;
+        self.frames_read += 1;
+        let reported = self.logged_pts(n).map(|pts| {
+            let first = *self.first_pts.get_or_insert(pts);
+            Duration::from_secs_f64((pts - first).max(0.0))
+        });
+        let pts = match (reported, self.last_pts) {
+            (Some(pts), Some(last)) => pts.max(last),
+            (Some(pts), None) => pts,
+            (None, Some(last)) => last + self.frame_delay,
+            (None, None) => Duration::ZERO,
+        };
+        self.last_pts = Some(pts);
+        pts
+    }
+
+    /// The time logged for frame `n`. Entries for earlier frames are skipped,
+    /// and one for a later frame is kept until that frame is read.
+    fn logged_pts(&mut self, n: u64) -> Option<f64> {
+        loop {
+            let (logged_n, pts) = match self.pending.take() {
+                Some(entry) => entry,
+                None => self.logged.recv().ok()?,
+            };
+            if logged_n == n {
+                return pts;
             }
-        } {
-            if let Ok(entries) = fs::read_dir(&self.frames_dir) {
-                let count = entries.filter_map(Result::ok).count() as u64;
-                pb.set_position(count.min(self.total_frames));
+            if logged_n > n {
+                self.pending = Some((logged_n, pts));
+                return None;
             }
-            std::thread::sleep(Duration::from_millis(200));
         }
+    }
+}
 
-        let output = child.wait_with_output().map_err(|e| {
+/// Raw frames decoded by an ffmpeg child process, read one at a time so only
+/// the frames currently being converted are kept in memory.
+pub struct FrameStream {
+    child: Child,
+    reader: BufReader<ChildStdout>,
+    width: u32,
+    height: u32,
+    timestamps: FrameTimestamps,
+    stderr_reader: Option<JoinHandle<String>>,
+    finished: bool,
+}
+
+impl FrameStream {
+    /// Waits for ffmpeg to exit and reports a failure if it did not succeed.
+    pub fn finish(mut self) -> Result<(), AppError> {
+        self.finished = true;
+        // Draining stderr to its end lets ffmpeg exit
+        let stderr_output = self
+            .stderr_reader
+            .take()
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
+        let status = self.child.wait().map_err(|e| {
             AppError::FFmpeg(format!(
                 "Failed waiting for ffmpeg final status (frames): {}",
                 e
             ))
         })?;
+        log::debug!("FFmpeg frame extraction finished with status: {}", status);
 
-        pb.set_position(self.total_frames);
-        pb.finish_and_clear();
-
-        if !output.status.success() {
-            let stderr_output = String::from_utf8_lossy(&output.stderr);
+        if !status.success() {
             log::error!("FFmpeg frame extraction failed: {}", stderr_output);
             return Err(AppError::FFmpeg(format!(
                 "Frame extraction failed (code {}): {}",
-                output.status.code().unwrap_or(-1),
+                status.code().unwrap_or(-1),
                 stderr_output
             )));
         }
+        Ok(())
+    }
+}
 
-        let mut paths: Vec<_> = fs::read_dir(self.frames_dir.path())
-            .map_err(|e| AppError::Io {
+impl Iterator for FrameStream {
+    /// A decoded frame with its presentation time.
+    type Item = Result<(RgbImage, Duration), AppError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = vec![0u8; (self.width * self.height * 3) as usize];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => {
+                let img = RgbImage::from_raw(self.width, self.height, buf)?;
+                Some(Ok((img, self.timestamps.next())))
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(AppError::Io {
                 source: e,
-                context: Some(self.frames_dir.path().display().to_string()),
-            })?
-            .filter_map(|e| e.ok().map(|e| e.path()))
-            .filter(|p| p.extension().map_or(false, |ext| ext == "png"))
-            .collect();
-        paths.sort();
-        Ok(paths)
+                context: Some("Reading frame from ffmpeg".to_string()),
+            })),
+        }
+    }
+}
+
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        if !self.finished {
+            debug!("Frame stream dropped early, killing ffmpeg");
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
     }
 }
 
-fn parse_fps(s: &str) -> f32 {
-    if let Some((num, den)) = s.split_once('/') {
-        num.parse::<f32>().unwrap_or(30.0) / den.parse::<f32>().unwrap_or(1.0)
+/// Parses a sample aspect ratio such as `64:45`. Unknown ratios, reported
+/// as `0:1` or `N/A`, give `None`.
+fn parse_aspect_ratio(s: &str) -> Option<f64> {
+    let (num, den) = s.split_once(':')?;
+    let ratio = num.parse::<f64>().ok()? / den.parse::<f64>().ok()?;
+    (ratio.is_finite() && ratio > 0.0).then_some(ratio)
+}
+
+/// Size of the picture as a player shows it: non-square samples stretched
+/// horizontally and the sides swapped for a quarter turn.
+fn display_size(width: u32, height: u32, sample_aspect_ratio: f64, rotation: u32) -> (u32, u32) {
+    let width = ((width as f64 * sample_aspect_ratio).round() as u32).max(1);
+    if rotation % 180 == 90 {
+        (height, width)
     } else {
-        s.parse::<f32>().unwrap_or(30.0)
+        (width, height)
+    }
+}
+
+/// Parses an ffprobe rate such as `30000/1001`, rejecting zero and
+/// undefined rates like `0/0`.
+fn parse_fps(s: &str) -> Option<f32> {
+    let fps = match s.split_once('/') {
+        Some((num, den)) => num.parse::<f32>().ok()? / den.parse::<f32>().ok()?,
+        None => s.parse::<f32>().ok()?,
+    };
+    (fps.is_finite() && fps > 0.0).then_some(fps)
+}
+
+/// The properties of the first video stream that playback depends on.
+#[derive(Debug, PartialEq)]
+struct StreamProperties {
+    frame_rate: f32,
+    total_frames: u64,
+    duration: Duration,
+    width: u32,
+    height: u32,
+    rotation: u32,
+}
+
+impl StreamProperties {
+    /// Reads the properties from ffprobe's report on `video_path`, with the
+    /// frame count and duration limited to `range`. Values ffprobe could not
+    /// determine are derived from the others where possible.
+    fn from_probe(
+        probe: ProbeOutput,
+        range: TimeRange,
+        video_path: &Path,
+    ) -> Result<Self, AppError> {
+        let invalid =
+            |reason: &str| AppError::InvalidMetadata(video_path.to_path_buf(), reason.to_string());
+        let stream = probe
+            .streams
+            .into_iter()
+            .next()
+            .ok_or_else(|| invalid("no video stream"))?;
+
+        let (coded_width, coded_height) = match (stream.width, stream.height) {
+            (Some(w), Some(h)) if w > 0 && h > 0 => (w, h),
+            _ => return Err(invalid("missing frame size")),
+        };
+        // ffmpeg rotates frames by the display matrix before any filter runs,
+        // so only the size the frames are fitted from has to follow it
+        let rotation = stream.rotation();
+        let sample_aspect_ratio = stream
+            .sample_aspect_ratio
+            .as_deref()
+            .and_then(parse_aspect_ratio)
+            .unwrap_or(1.0);
+        let (width, height) =
+            display_size(coded_width, coded_height, sample_aspect_ratio, rotation);
+        if (width, height) != (coded_width, coded_height) {
+            debug!(
+                "Frames of {}x{} are displayed as {}x{} (rotation {}, sample aspect ratio {:.3})",
+                coded_width, coded_height, width, height, rotation, sample_aspect_ratio
+            );
+        }
+        // r_frame_rate is only a guess at the time base for Matroska and WebM,
+        // often 1000/1, so the average rate is preferred
+        let frame_rate = [&stream.avg_frame_rate, &stream.r_frame_rate]
+            .into_iter()
+            .flatten()
+            .find_map(|rate| parse_fps(rate))
+            .ok_or_else(|| invalid("missing or invalid frame rate"))?;
+        // The stream may report N/A where the container knows the duration
+        let known = |d: &&str| *d != "N/A";
+        let duration = match stream.duration.as_deref().filter(known).or(probe
+            .format
+            .as_ref()
+            .and_then(|f| f.duration.as_deref())
+            .filter(known))
+        {
+            Some(d) => Some(d.parse::<f64>().map_err(|e| AppError::ParseFloat {
+                source: e,
+                context: Some("duration parse".to_string()),
+            })?),
+            None => None,
+        };
+        let total_frames: u64 = match stream.nb_frames.as_deref().filter(|n| *n != "N/A") {
+            Some(n) => n.parse().map_err(|e| AppError::ParseInt {
+                source: e,
+                context: Some("total_frames parse".to_string()),
+            })?,
+            // Matroska, WebM and live streams do not store a frame count, so
+            // it is estimated from the average rate
+            None => match duration {
+                Some(d) => (d * frame_rate as f64).round() as u64,
+                None => return Err(invalid("neither frame count nor duration is known")),
+            },
+        };
+        let duration = match duration {
+            Some(d) => Duration::from_secs_f64(d),
+            None => Duration::from_secs_f64(total_frames as f64 / frame_rate as f64),
+        };
+        let (total_frames, duration) = if range.is_full() {
+            (total_frames, duration)
+        } else {
+            if range.start >= duration {
+                return Err(AppError::InvalidTimeRange(format!(
+                    "start {:.3}s is past the end of the video ({:.3}s)",
+                    range.start.as_secs_f64(),
+                    duration.as_secs_f64()
+                )));
+            }
+            let clip = range.end.map_or(duration, |end| end.min(duration)) - range.start;
+            let frames = (clip.as_secs_f64() * frame_rate as f64).round() as u64;
+            (frames, clip)
+        };
+
+        Ok(StreamProperties {
+            frame_rate,
+            total_frames,
+            duration,
+            width,
+            height,
+            rotation,
+        })
+    }
+}
+
+/// The parts of `ffprobe -of json` output that are asked for. Numbers that can
+/// be `N/A` are reported as strings.
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    width: Option<u32>,
+    height: Option<u32>,
+    sample_aspect_ratio: Option<String>,
+    r_frame_rate: Option<String>,
+    avg_frame_rate: Option<String>,
+    nb_frames: Option<String>,
+    duration: Option<String>,
+    #[serde(default)]
+    tags: ProbeTags,
+    #[serde(default)]
+    side_data_list: Vec<ProbeSideData>,
+}
+
+impl ProbeStream {
+    /// Clockwise rotation in degrees, normalized to 0, 90, 180 or 270. The
+    /// display matrix takes precedence over the older `rotate` tag.
+    fn rotation(&self) -> u32 {
+        let degrees = self
+            .side_data_list
+            .iter()
+            .find_map(|d| d.rotation)
+            // The display matrix holds the counter-clockwise angle
+            .map(|r| -r)
+            .or_else(|| self.tags.rotate.as_deref()?.parse().ok())
+            .unwrap_or(0.0);
+        ((degrees / 90.0).round() as i64).rem_euclid(4) as u32 * 90
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct ProbeTags {
+    rotate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeSideData {
+    rotation: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    duration: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn showinfo_frame_lines_are_parsed() {
+        let line = "[Parsed_showinfo_1 @ 0x6000024d8000] [info] n:  42 pts:  21504 pts_time:1.68    duration:    512 duration_time:0.04    fmt:yuv420p cl:left sar:1/1 s:160x90 i:P iskey:0 type:P checksum:5C0B6A1D plane_checksum:[9F8E7A51 6C1D2B3E 2E4F5A6B] mean:[98 126 131] stdev:[54.2 3.9 6.1]";
+        assert_eq!(parse_showinfo_frame(line), Some((42, Some(1.68))));
+
+        // ffmpeg before 6.0, and without a log level prefix
+        let line = "[Parsed_showinfo_2 @ 0x55d0c1e4a2c0] n:   0 pts:      0 pts_time:0       pos:       48 fmt:rgb24 sar:1/1 s:80x45 i:P iskey:1 type:I checksum:0E1D2C3B plane_checksum:[0E1D2C3B] mean:[17] stdev:[12.0]";
+        assert_eq!(parse_showinfo_frame(line), Some((0, Some(0.0))));
+    }
+
+    #[test]
+    fn showinfo_frames_without_timestamp_keep_their_number() {
+        let line = "[Parsed_showinfo_1 @ 0x6000024d8000] [info] n:   7 pts:NOPTS pts_time:NOPTS duration:    512 duration_time:0.04    fmt:yuv420p cl:left sar:1/1 s:160x90 i:P iskey:0 type:B checksum:1A2B3C4D";
+        assert_eq!(parse_showinfo_frame(line), Some((7, None)));
+
+        let line = "[Parsed_showinfo_1 @ 0x6000024d8000] [info] n:   8 pts:  4608 pts_time:nan duration:    512";
+        assert_eq!(parse_showinfo_frame(line), Some((8, None)));
+    }
+
+    #[test]
+    fn other_log_lines_are_not_frames() {
+        for line in [
+            "[Parsed_showinfo_1 @ 0x6000024d8000] [info] config in time_base: 1/12800, frame_rate: 25/1",
+            "[Parsed_showinfo_1 @ 0x6000024d8000] [info] config out time_base: 0/0, frame_rate: 0/0",
+            "[Parsed_showinfo_1 @ 0x6000024d8000] [info]   side data - display matrix: rotation of -90.00 degrees",
+            "[Parsed_showinfo_1 @ 0x6000024d8000] [info] color_range:tv color_space:bt709 color_primaries:bt709 color_trc:bt709",
+            "[h264 @ 0x6000024dc000] [error] Invalid NAL unit size (2711 > 1093).",
+        ] {
+            assert_eq!(parse_showinfo_frame(line), None, "{}", line);
+        }
+    }
+
+    const DELAY: Duration = Duration::from_millis(40);
+
+    /// Timestamps of `count` frames read with `logged` as the showinfo
+    /// entries, in milliseconds.
+    fn timestamps(logged: &[(u64, Option<f64>)], count: usize) -> Vec<u128> {
+        let (tx, rx) = mpsc::channel();
+        for &entry in logged {
+            tx.send(entry).unwrap();
+        }
+        drop(tx);
+        let mut timestamps = FrameTimestamps::new(rx, DELAY);
+        (0..count).map(|_| timestamps.next().as_millis()).collect()
+    }
+
+    #[test]
+    fn timestamps_start_from_the_first_frame() {
+        let logged = [(0, Some(10.0)), (1, Some(10.04)), (2, Some(10.1))];
+        assert_eq!(timestamps(&logged, 3), [0, 40, 100]);
+    }
+
+    #[test]
+    fn frames_without_timestamp_follow_the_previous_frame() {
+        let logged = [(0, Some(1.0)), (1, None), (2, None), (3, Some(1.2))];
+        assert_eq!(timestamps(&logged, 4), [0, 40, 80, 200]);
+
+        // Nothing logged at all, e.g. once ffmpeg has exited
+        assert_eq!(timestamps(&[], 3), [0, 40, 80]);
+    }
+
+    #[test]
+    fn missing_log_lines_do_not_shift_later_frames() {
+        let logged = [(0, Some(0.0)), (2, Some(0.5)), (3, Some(0.6))];
+        assert_eq!(timestamps(&logged, 4), [0, 40, 500, 600]);
+
+        let repeated = [(0, Some(0.0)), (0, Some(0.0)), (1, Some(0.3))];
+        assert_eq!(timestamps(&repeated, 2), [0, 300]);
+    }
+
+    #[test]
+    fn timestamps_never_go_backwards() {
+        let logged = [(0, Some(2.0)), (1, Some(2.5)), (2, Some(1.0))];
+        assert_eq!(timestamps(&logged, 3), [0, 500, 500]);
+
+        // A frame before the first timestamped one is clamped to the start
+        let logged = [(0, None), (1, Some(5.0)), (2, Some(4.9))];
+        assert_eq!(timestamps(&logged, 3), [0, 0, 0]);
+    }
+
+    #[test]
+    fn frame_rates_are_parsed() {
+        assert_eq!(parse_fps("25/1"), Some(25.0));
+        assert!((parse_fps("30000/1001").unwrap() - 29.97).abs() < 0.001);
+        assert_eq!(parse_fps("24"), Some(24.0));
+        for rate in ["0/0", "0/1", "30/0", "-25/1", "N/A", ""] {
+            assert_eq!(parse_fps(rate), None, "{}", rate);
+        }
+    }
+
+    #[test]
+    fn sample_aspect_ratios_are_parsed() {
+        assert_eq!(parse_aspect_ratio("1:1"), Some(1.0));
+        assert!((parse_aspect_ratio("64:45").unwrap() - 1.4222).abs() < 0.001);
+        for sar in ["0:1", "1:0", "N/A", "4/3"] {
+            assert_eq!(parse_aspect_ratio(sar), None, "{}", sar);
+        }
+    }
+
+    #[test]
+    fn quarter_turns_swap_the_display_size() {
+        assert_eq!(display_size(1920, 1080, 1.0, 0), (1920, 1080));
+        assert_eq!(display_size(1920, 1080, 1.0, 90), (1080, 1920));
+        assert_eq!(display_size(1920, 1080, 1.0, 180), (1920, 1080));
+        assert_eq!(display_size(1920, 1080, 1.0, 270), (1080, 1920));
+    }
+
+    #[test]
+    fn sample_aspect_ratio_stretches_the_width() {
+        // DVD PAL widescreen: 720x576 samples shown at 16:9
+        assert_eq!(display_size(720, 576, 64.0 / 45.0, 0), (1024, 576));
+        assert_eq!(display_size(720, 480, 8.0 / 9.0, 0), (640, 480));
+        // The stretch applies to the coded width, before the sides swap
+        assert_eq!(display_size(720, 576, 64.0 / 45.0, 90), (576, 1024));
+        assert_eq!(display_size(1, 1, 0.1, 0), (1, 1));
+    }
+
+    fn probe(json: &str) -> ProbeOutput {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn properties(json: &str, range: TimeRange) -> Result<StreamProperties, AppError> {
+        StreamProperties::from_probe(probe(json), range, Path::new("clip"))
+    }
+
+    #[test]
+    fn rotation_prefers_display_matrix_over_tag() {
+        let rotation = |json| probe(json).streams[0].rotation();
+        assert_eq!(rotation(r#"{"streams": [{}]}"#), 0);
+        assert_eq!(
+            rotation(
+                r#"{"streams": [{"side_data_list": [{"side_data_type": "Display Matrix", "rotation": -90}]}]}"#
+            ),
+            90
+        );
+        assert_eq!(
+            rotation(
+                r#"{"streams": [{"side_data_list": [{"side_data_type": "Display Matrix", "rotation": 90}]}]}"#
+            ),
+            270
+        );
+        assert_eq!(
+            rotation(r#"{"streams": [{"tags": {"rotate": "180"}}]}"#),
+            180
+        );
+        assert_eq!(
+            rotation(
+                r#"{"streams": [{"tags": {"rotate": "90"}, "side_data_list": [{"rotation": 0}]}]}"#
+            ),
+            0
+        );
+    }
+
+    // Captured with `ffprobe -v error -select_streams v:0 -show_entries ...
+    // -of json`, as in `VideoInfo::analyze`
+    const MKV_PROBE: &str = r#"{
+        "programs": [],
+        "streams": [{
+            "width": 1920,
+            "height": 1080,
+            "sample_aspect_ratio": "1:1",
+            "r_frame_rate": "24000/1001",
+            "avg_frame_rate": "24000/1001",
+            "tags": {"DURATION": "00:00:10.010000000"}
+        }],
+        "format": {"duration": "10.010000"}
+    }"#;
+
+    const WEBM_PROBE: &str = r#"{
+        "programs": [],
+        "streams": [{
+            "width": 640,
+            "height": 360,
+            "r_frame_rate": "30/1",
+            "avg_frame_rate": "0/0",
+            "nb_frames": "N/A",
+            "duration": "N/A"
+        }],
+        "format": {"duration": "4.000000"}
+    }"#;
+
+    const ROTATED_MP4_PROBE: &str = r#"{
+        "programs": [],
+        "streams": [{
+            "width": 1920,
+            "height": 1080,
+            "sample_aspect_ratio": "1:1",
+            "r_frame_rate": "30/1",
+            "avg_frame_rate": "30/1",
+            "duration": "10.000000",
+            "nb_frames": "300",
+            "side_data_list": [{
+                "side_data_type": "Display Matrix",
+                "displaymatrix": "\n00000000:            0       65536           0\n00000001:       -65536           0           0\n00000002:            0           0  1073741824\n",
+                "rotation": -90
+            }]
+        }],
+        "format": {"duration": "10.010000"}
+    }"#;
+
+    #[test]
+    fn frame_count_is_estimated_without_nb_frames() {
+        let mkv = properties(MKV_PROBE, TimeRange::default()).unwrap();
+        assert!((mkv.frame_rate - 23.976).abs() < 0.001);
+        assert_eq!(mkv.total_frames, 240);
+        assert_eq!(mkv.duration, Duration::from_millis(10010));
+
+        // The undefined average rate falls back to r_frame_rate
+        let webm = properties(WEBM_PROBE, TimeRange::default()).unwrap();
+        assert_eq!(webm.frame_rate, 30.0);
+        assert_eq!(webm.total_frames, 120);
+        assert_eq!(webm.duration, Duration::from_secs(4));
+    }
+
+    #[test]
+    fn stored_frame_count_and_rotation_are_used() {
+        let mp4 = properties(ROTATED_MP4_PROBE, TimeRange::default()).unwrap();
+        assert_eq!(
+            mp4,
+            StreamProperties {
+                frame_rate: 30.0,
+                total_frames: 300,
+                duration: Duration::from_secs(10),
+                width: 1080,
+                height: 1920,
+                rotation: 90,
+            }
+        );
+    }
+
+    #[test]
+    fn ranges_limit_frame_count_and_duration() {
+        let range = TimeRange::new(Duration::from_secs(1), Some(Duration::from_secs(3))).unwrap();
+        let clip = properties(WEBM_PROBE, range).unwrap();
+        assert_eq!(clip.total_frames, 60);
+        assert_eq!(clip.duration, Duration::from_secs(2));
+
+        // An end past the video stops at the end of the video
+        let range = TimeRange::new(Duration::from_secs(3), Some(Duration::from_secs(9))).unwrap();
+        assert_eq!(
+            properties(WEBM_PROBE, range).unwrap().duration,
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn start_past_the_end_is_rejected() {
+        for start in [4, 5] {
+            let range = TimeRange::new(Duration::from_secs(start), None).unwrap();
+            assert!(
+                matches!(
+                    properties(WEBM_PROBE, range),
+                    Err(AppError::InvalidTimeRange(_))
+                ),
+                "start {}",
+                start
+            );
+        }
+    }
+
+    #[test]
+    fn unusable_probe_output_is_rejected() {
+        let no_rate = r#"{"streams": [{"width": 640, "height": 360, "r_frame_rate": "0/0", "avg_frame_rate": "0/0", "nb_frames": "10"}]}"#;
+        let no_length = r#"{"streams": [{"width": 640, "height": 360, "avg_frame_rate": "25/1", "nb_frames": "N/A"}], "format": {"duration": "N/A"}}"#;
+        let no_size = r#"{"streams": [{"width": 0, "height": 360, "avg_frame_rate": "25/1", "nb_frames": "10"}]}"#;
+        for json in [r#"{"streams": []}"#, no_rate, no_length, no_size] {
+            assert!(
+                matches!(
+                    properties(json, TimeRange::default()),
+                    Err(AppError::InvalidMetadata(..))
+                ),
+                "{}",
+                json
+            );
+        }
     }
 }